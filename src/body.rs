@@ -0,0 +1,133 @@
+use std::{fmt, ops::Deref, pin::Pin, sync::Arc};
+
+use smol::stream::{self, Stream, StreamExt};
+
+pub type BodyStream = Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>;
+
+/// Body of a response
+#[derive(Default)]
+pub enum Body {
+    #[default]
+    Empty,
+    /// Sent at once, with a `Content-Length`
+    Full(Bytes),
+    /// Sent with `Transfer-Encoding: chunked`, each chunk as soon as it is produced
+    Stream(BodyStream),
+}
+
+impl Body {
+    pub fn from_stream<St, T>(stream: St) -> Self
+    where
+        St: Stream<Item = T> + Send + 'static,
+        T: Into<Vec<u8>> + 'static,
+    {
+        Self::Stream(Box::pin(stream.map(Into::into)))
+    }
+
+    /// Streams the chunks yielded by `chunks`, which is only iterated while the response is sent
+    pub fn from_chunks<I>(chunks: I) -> Self
+    where
+        I: IntoIterator,
+        I::IntoIter: Send + 'static,
+        I::Item: Into<Vec<u8>> + 'static,
+    {
+        Self::from_stream(stream::iter(chunks))
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Empty => write!(f, "Empty"),
+            Body::Full(body) => f.debug_tuple("Full").field(body).finish(),
+            Body::Stream(_) => write!(f, "Stream(..)"),
+        }
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(body: Bytes) -> Self {
+        Self::Full(body)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(body: Vec<u8>) -> Self {
+        Self::Full(body.into())
+    }
+}
+
+impl From<String> for Body {
+    fn from(body: String) -> Self {
+        Self::Full(body.into())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(body: &str) -> Self {
+        Self::Full(body.to_string().into())
+    }
+}
+
+/// Bytes of a body, either owned, shared between responses or embedded in the binary
+#[derive(Clone)]
+pub enum Bytes {
+    Static(&'static [u8]),
+    Owned(Vec<u8>),
+    /// Cloning only increments a reference count, useful for buffers sent many times
+    Shared(Arc<[u8]>),
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Bytes::Static(bytes) => bytes,
+            Bytes::Owned(bytes) => bytes,
+            Bytes::Shared(bytes) => bytes,
+        }
+    }
+}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(self))
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl From<&'static [u8]> for Bytes {
+    fn from(bytes: &'static [u8]) -> Self {
+        Self::Static(bytes)
+    }
+}
+
+impl From<&'static str> for Bytes {
+    fn from(bytes: &'static str) -> Self {
+        Self::Static(bytes.as_bytes())
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Owned(bytes)
+    }
+}
+
+impl From<String> for Bytes {
+    fn from(bytes: String) -> Self {
+        Self::Owned(bytes.into_bytes())
+    }
+}
+
+impl From<Arc<[u8]>> for Bytes {
+    fn from(bytes: Arc<[u8]>) -> Self {
+        Self::Shared(bytes)
+    }
+}
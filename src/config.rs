@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+/// Connection settings used by [`crate::serve_with_config`]
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub(crate) idle_timeout: Duration,
+    pub(crate) request_timeout: Duration,
+    pub(crate) max_requests_per_connection: usize,
+    pub(crate) max_body_size: usize,
+    pub(crate) max_websocket_message_size: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerConfig {
+    pub fn new() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(10),
+            max_requests_per_connection: 100,
+            max_body_size: 2 * 1024 * 1024,
+            max_websocket_message_size: 16 * 1024 * 1024,
+        }
+    }
+
+    /// How long a persistent connection may stay without receiving data before being closed
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// How long a client has to send a whole request, headers and body, once it started sending
+    /// it. Slower requests get a 408.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// How many requests are answered on a single connection before it is closed
+    pub fn with_max_requests_per_connection(mut self, max_requests: usize) -> Self {
+        self.max_requests_per_connection = max_requests;
+        self
+    }
+
+    /// How many bytes a request body may have, chunked ones once decoded. Bigger bodies get a 413.
+    pub fn with_max_body_size(mut self, max_size: usize) -> Self {
+        self.max_body_size = max_size;
+        self
+    }
+
+    /// How many bytes a WebSocket message may have once its fragments are joined, bigger ones
+    /// close the connection
+    pub fn with_max_websocket_message_size(mut self, max_size: usize) -> Self {
+        self.max_websocket_message_size = max_size;
+        self
+    }
+}
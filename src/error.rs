@@ -1,5 +1,7 @@
 use crate::{
-    response::{IntoResponse, Response},
+    header,
+    method::Method,
+    response::{IntoResponse, Response, ResponseBuilder},
     status_code::StatusCode,
 };
 
@@ -8,12 +10,161 @@ pub enum Error {
     TcpStreamError(std::io::Error),
 }
 
+/// A route that can't be registered, reported when building the [`Router`](crate::router::Router)
+#[derive(Debug)]
+pub enum RouteError {
+    /// A handler is already registered for this method and path
+    Duplicate(Method, String),
+    /// A catch-all segment is followed by other segments
+    CatchAllNotLast(String),
+    /// An optional segment is followed by a required one
+    OptionalNotLast(String),
+    /// A dynamic segment is named differently from the one already registered at the same level
+    Ambiguous {
+        path: String,
+        name: String,
+        existing: String,
+    },
+}
+
+impl std::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Duplicate(method, path) => {
+                write!(f, "A handler is already registered for {method} {path}")
+            }
+            Self::CatchAllNotLast(path) => {
+                write!(f, "Catch-all segment must be the last one in {path}")
+            }
+            Self::OptionalNotLast(path) => {
+                write!(f, "Optional segments must be at the end of {path}")
+            }
+            Self::Ambiguous {
+                path,
+                name,
+                existing,
+            } => write!(
+                f,
+                "Segment `{name}` of {path} is ambiguous with the `{existing}` segment of another route"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+/// A number that can't be a status code as it isn't between 100 and 599, see RFC 9110
+#[derive(Debug)]
+pub struct InvalidStatusCode(pub u16);
+
+impl std::fmt::Display for InvalidStatusCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid status code : {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidStatusCode {}
+
+/// A header name that is not a token, as defined by RFC 9110
+#[derive(Debug)]
+pub struct InvalidHeaderName(pub String);
+
+impl std::fmt::Display for InvalidHeaderName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid header name : {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidHeaderName {}
+
+/// A header value containing a control character, e.g. CR or LF
+#[derive(Debug)]
+pub struct InvalidHeaderValue(pub String);
+
+impl std::fmt::Display for InvalidHeaderValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid header value : {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidHeaderValue {}
+
+/// An error ending a WebSocket connection, see [`WebSocket`](crate::ws::WebSocket)
+#[derive(Debug)]
+pub enum WebSocketError {
+    Io(std::io::Error),
+    /// The client broke RFC 6455, e.g. sent an unmasked frame or an unknown opcode
+    Protocol(&'static str),
+    /// A text message or close reason is not valid UTF-8
+    InvalidUtf8,
+    /// A message is bigger than the configured maximum size
+    MessageTooBig,
+    /// The close handshake is done, or the connection was closed because of an error
+    ConnectionClosed,
+}
+
+impl std::fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "WebSocket I/O error : {e}"),
+            Self::Protocol(reason) => write!(f, "WebSocket protocol error : {reason}"),
+            Self::InvalidUtf8 => write!(f, "Invalid UTF-8 in a WebSocket message"),
+            Self::MessageTooBig => write!(f, "WebSocket message too big"),
+            Self::ConnectionClosed => write!(f, "WebSocket connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for WebSocketError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for WebSocketError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
 #[derive(Debug)]
 pub enum HttpError {
     //400 Bad Request
     MissingBytesBody,
     MissingStringBody,
     InvalidQuery,
+    MalformedRequestLine,
+    InvalidHeader,
+    ReadRequestError(std::io::Error),
+    InvalidChunk,
+    ConflictingBodyLength,
+    InvalidPathParam(String),
+    InvalidQueryParams(String),
+    InvalidJson(String),
+    InvalidWebSocketHandshake(String),
+    /// An HTTP/1.1 request without exactly one Host header
+    InvalidHost,
+    /// A Content-Length which isn't only digits, e.g. `+5`
+    InvalidContentLength(String),
+
+    //408 Request Timeout
+    /// The client didn't send the whole request in time
+    RequestTimeout,
+
+    //413 Content Too Large
+    /// The body is bigger than the maximum body size of the server
+    BodyTooLarge,
+
+    //414 URI Too Long
+    /// The request line is longer than the maximum line length
+    RequestLineTooLong,
+
+    //431 Request Header Fields Too Large
+    /// A header line is too long or there are too many headers
+    HeadersTooLarge,
 
     //411 Length Required
     LengthMissing,
@@ -21,11 +172,25 @@ pub enum HttpError {
 
     //415 Unsupported Media Type
     ContentTypeMissing,
+    UnexpectedContentType(String),
     InvalidBytesBody(std::io::Error),
     InvalidStringBody(std::string::FromUtf8Error),
 
+    //426 Upgrade Required
+    UnsupportedWebSocketVersion(String),
+
     //500 Internal Server Error
     GetPeerAddrError(std::io::Error),
+    /// The route doesn't have as many parameters as the `Path` extractor expects
+    WrongPathParamCount(usize, usize),
+    SerializeJsonError(String),
+
+    //501 Not Implemented
+    UnknownMethod(String),
+    UnsupportedTransferEncoding(String),
+
+    //505 HTTP Version Not Supported
+    UnsupportedHttpVersion(String),
 }
 
 impl IntoResponse for HttpError {
@@ -39,6 +204,22 @@ impl IntoResponse for HttpError {
                 println!("[WARN] Content-Type missing");
                 StatusCode::UnsupportedMediaType.into_response()
             }
+            HttpError::RequestTimeout => {
+                println!("[WARN] Request timeout");
+                StatusCode::RequestTimeout.into_response()
+            }
+            HttpError::BodyTooLarge => {
+                println!("[WARN] Body too large");
+                StatusCode::ContentTooLarge.into_response()
+            }
+            HttpError::RequestLineTooLong => {
+                println!("[WARN] Request line too long");
+                StatusCode::UriTooLong.into_response()
+            }
+            HttpError::HeadersTooLarge => {
+                println!("[WARN] Headers too large");
+                StatusCode::RequestHeaderFieldsTooLarge.into_response()
+            }
             HttpError::LengthMissing => {
                 println!("[WARN] Length missing");
                 StatusCode::LengthRequired.into_response()
@@ -67,6 +248,81 @@ impl IntoResponse for HttpError {
                 println!("[WARN] Invalid query");
                 StatusCode::BadRequest.into_response()
             }
+            HttpError::MalformedRequestLine => {
+                println!("[WARN] Malformed request line");
+                StatusCode::BadRequest.into_response()
+            }
+            HttpError::InvalidHeader => {
+                println!("[WARN] Invalid header");
+                StatusCode::BadRequest.into_response()
+            }
+            HttpError::ReadRequestError(e) => {
+                println!("[WARN] Error reading the request : {e}");
+                StatusCode::BadRequest.into_response()
+            }
+            HttpError::InvalidChunk => {
+                println!("[WARN] Invalid chunk");
+                StatusCode::BadRequest.into_response()
+            }
+            HttpError::ConflictingBodyLength => {
+                println!("[WARN] Both Content-Length and Transfer-Encoding are set");
+                StatusCode::BadRequest.into_response()
+            }
+            HttpError::InvalidPathParam(name) => {
+                println!("[WARN] Invalid path parameter : {name}");
+                StatusCode::BadRequest.into_response()
+            }
+            HttpError::InvalidQueryParams(e) => {
+                println!("[WARN] Invalid query parameters : {e}");
+                StatusCode::BadRequest.into_response()
+            }
+            HttpError::InvalidJson(e) => {
+                println!("[WARN] Invalid JSON body : {e}");
+                StatusCode::BadRequest.into_response()
+            }
+            HttpError::InvalidWebSocketHandshake(reason) => {
+                println!("[WARN] Invalid WebSocket handshake : {reason}");
+                StatusCode::BadRequest.into_response()
+            }
+            HttpError::InvalidHost => {
+                println!("[WARN] Missing or repeated Host header");
+                StatusCode::BadRequest.into_response()
+            }
+            HttpError::InvalidContentLength(length) => {
+                println!("[WARN] Invalid Content-Length : {length}");
+                StatusCode::BadRequest.into_response()
+            }
+            HttpError::UnsupportedWebSocketVersion(version) => {
+                println!("[WARN] Unsupported WebSocket version : {version}");
+                ResponseBuilder::new()
+                    .with_status_code(StatusCode::UpgradeRequired)
+                    .append_header(header::SEC_WEBSOCKET_VERSION, "13")
+                    .build()
+            }
+            HttpError::UnexpectedContentType(content_type) => {
+                println!("[WARN] Unexpected Content-Type : {content_type}");
+                StatusCode::UnsupportedMediaType.into_response()
+            }
+            HttpError::WrongPathParamCount(expected, found) => {
+                eprintln!("[ERROR] Expected {expected} path parameters, the route has {found}");
+                StatusCode::InternalServerError.into_response()
+            }
+            HttpError::SerializeJsonError(e) => {
+                eprintln!("[ERROR] Error serializing JSON : {e}");
+                StatusCode::InternalServerError.into_response()
+            }
+            HttpError::UnsupportedTransferEncoding(encoding) => {
+                println!("[WARN] Unsupported Transfer-Encoding : {encoding}");
+                StatusCode::NotImplemented.into_response()
+            }
+            HttpError::UnknownMethod(method) => {
+                println!("[WARN] Unknown method : {method}");
+                StatusCode::NotImplemented.into_response()
+            }
+            HttpError::UnsupportedHttpVersion(version) => {
+                println!("[WARN] Unsupported HTTP version : {version}");
+                StatusCode::HttpVersionNotSupported.into_response()
+            }
         }
     }
 }
@@ -77,23 +333,81 @@ impl HttpError {
             Self::MissingBytesBody => "Missing bytes body".to_string(),
             Self::MissingStringBody => "Missing String body".to_string(),
             Self::InvalidQuery => "Invalid query".to_string(),
+            Self::MalformedRequestLine => "Malformed request line".to_string(),
+            Self::InvalidHeader => "Invalid header".to_string(),
+            Self::ReadRequestError(e) => format!("Error reading the request : {e}"),
+            Self::RequestTimeout => "Request timeout".to_string(),
+            Self::BodyTooLarge => "Body too large".to_string(),
+            Self::RequestLineTooLong => "Request line too long".to_string(),
+            Self::HeadersTooLarge => "Headers too large".to_string(),
             Self::LengthMissing => "Length missing".to_string(),
             Self::InvalidLength(e) => format!("Invalid length : {e}"),
             Self::ContentTypeMissing => "Content-Type header missing".to_string(),
             Self::InvalidBytesBody(e) => format!("Invalid bytes body : {e}"),
             Self::InvalidStringBody(e) => format!("Invali String body : {e}"),
             Self::GetPeerAddrError(e) => format!("Get peer addr error : {e}"),
+            Self::InvalidChunk => "Invalid chunk".to_string(),
+            Self::InvalidPathParam(name) => format!("Invalid path parameter : {name}"),
+            Self::InvalidQueryParams(e) => format!("Invalid query parameters : {e}"),
+            Self::InvalidJson(e) => format!("Invalid JSON body : {e}"),
+            Self::InvalidWebSocketHandshake(reason) => {
+                format!("Invalid WebSocket handshake : {reason}")
+            }
+            Self::InvalidHost => "Missing or repeated Host header".to_string(),
+            Self::InvalidContentLength(length) => format!("Invalid Content-Length : {length}"),
+            Self::UnsupportedWebSocketVersion(version) => {
+                format!("Unsupported WebSocket version : {version}")
+            }
+            Self::UnexpectedContentType(content_type) => {
+                format!("Unexpected Content-Type : {content_type}")
+            }
+            Self::WrongPathParamCount(expected, found) => {
+                format!("Expected {expected} path parameters, the route has {found}")
+            }
+            Self::SerializeJsonError(e) => format!("Error serializing JSON : {e}"),
+            Self::ConflictingBodyLength => {
+                "Both Content-Length and Transfer-Encoding are set".to_string()
+            }
+            Self::UnsupportedTransferEncoding(encoding) => {
+                format!("Unsupported Transfer-Encoding : {encoding}")
+            }
+            Self::UnknownMethod(method) => format!("Unknown method : {method}"),
+            Self::UnsupportedHttpVersion(version) => {
+                format!("Unsupported HTTP version : {version}")
+            }
         };
         match self {
             Self::MissingBytesBody
             | Self::MissingStringBody
             | Self::InvalidQuery
+            | Self::MalformedRequestLine
+            | Self::InvalidHeader
+            | Self::ReadRequestError(..)
+            | Self::InvalidChunk
+            | Self::InvalidPathParam(..)
+            | Self::InvalidQueryParams(..)
+            | Self::InvalidJson(..)
+            | Self::InvalidWebSocketHandshake(..)
+            | Self::InvalidHost
+            | Self::InvalidContentLength(..)
+            | Self::UnsupportedWebSocketVersion(..)
+            | Self::UnexpectedContentType(..)
+            | Self::ConflictingBodyLength
+            | Self::UnsupportedTransferEncoding(..)
+            | Self::UnknownMethod(..)
+            | Self::UnsupportedHttpVersion(..)
+            | Self::RequestTimeout
+            | Self::BodyTooLarge
+            | Self::RequestLineTooLong
+            | Self::HeadersTooLarge
             | Self::LengthMissing
             | Self::InvalidLength(..)
             | Self::ContentTypeMissing
             | Self::InvalidBytesBody(..)
             | Self::InvalidStringBody(..) => println!("[WARN] {message}"),
-            Self::GetPeerAddrError(..) => println!("[ERROR] {message}"),
+            Self::GetPeerAddrError(..)
+            | Self::WrongPathParamCount(..)
+            | Self::SerializeJsonError(..) => println!("[ERROR] {message}"),
         }
     }
 }
@@ -0,0 +1,225 @@
+use std::{collections::HashMap, convert::Infallible, str::FromStr};
+
+use crate::{error::HttpError, request::Request, response::IntoResponse, HttpResult};
+
+/// Types that can be extracted from a request without consuming its body
+pub trait FromRequestParts<S: Clone>: Sized {
+    /// Sent back instead of calling the handler when the extraction fails
+    type Rejection: IntoResponse;
+
+    fn from_request_parts(req: &Request<S>) -> Result<Self, Self::Rejection>;
+}
+
+/// Types that can be extracted from a request, possibly consuming its body.
+///
+/// `M` only distinguishes the implementations for types also implementing [`FromRequestParts`]
+/// and is always inferred.
+pub trait FromRequest<S: Clone, M = ViaRequest>: Sized {
+    /// Sent back instead of calling the handler when the extraction fails
+    type Rejection: IntoResponse;
+
+    fn from_request(req: Request<S>) -> Result<Self, Self::Rejection>;
+}
+
+#[doc(hidden)]
+pub enum ViaParts {}
+
+#[doc(hidden)]
+pub enum ViaRequest {}
+
+impl<S: Clone, T: FromRequestParts<S>> FromRequest<S, ViaParts> for T {
+    type Rejection = T::Rejection;
+
+    fn from_request(req: Request<S>) -> Result<Self, Self::Rejection> {
+        T::from_request_parts(&req)
+    }
+}
+
+impl<S: Clone> FromRequest<S> for Request<S> {
+    type Rejection = Infallible;
+
+    fn from_request(req: Request<S>) -> Result<Self, Self::Rejection> {
+        Ok(req)
+    }
+}
+
+impl<S: Clone> FromRequest<S> for Vec<u8> {
+    type Rejection = HttpError;
+
+    fn from_request(mut req: Request<S>) -> Result<Self, Self::Rejection> {
+        req.bytes_body().ok_or(HttpError::MissingBytesBody)
+    }
+}
+
+impl<S: Clone> FromRequest<S> for String {
+    type Rejection = HttpError;
+
+    fn from_request(mut req: Request<S>) -> Result<Self, Self::Rejection> {
+        let Some(body) = req.bytes_body() else {
+            return Err(HttpError::MissingStringBody);
+        };
+        String::from_utf8(body).map_err(HttpError::InvalidStringBody)
+    }
+}
+
+/// Extracts a clone of the router state
+#[derive(Debug, Clone)]
+pub struct State<S>(pub S);
+
+impl<S: Clone> FromRequestParts<S> for State<S> {
+    type Rejection = Infallible;
+
+    fn from_request_parts(req: &Request<S>) -> Result<Self, Self::Rejection> {
+        Ok(State(req.state().clone()))
+    }
+}
+
+/// Extracts the path parameters of the matched route.
+///
+/// A single value is parsed from a route with a single parameter, a tuple from a route with as
+/// many parameters, in the same order.
+#[derive(Debug, Clone)]
+pub struct Path<T>(pub T);
+
+impl<S: Clone, T: FromPathParams> FromRequestParts<S> for Path<T> {
+    type Rejection = HttpError;
+
+    fn from_request_parts(req: &Request<S>) -> Result<Self, Self::Rejection> {
+        T::from_path_params(req.path_params()).map(Path)
+    }
+}
+
+/// Types that can be parsed from the path parameters of a route, used by [`Path`]
+pub trait FromPathParams: Sized {
+    fn from_path_params(params: &[(String, String)]) -> HttpResult<Self>;
+}
+
+fn parse_param<T: FromStr>((name, value): &(String, String)) -> HttpResult<T> {
+    value
+        .parse()
+        .map_err(|_| HttpError::InvalidPathParam(name.clone()))
+}
+
+macro_rules! impl_from_path_params_for_value {
+    ($($ty:ty),*) => {
+        $(
+            impl FromPathParams for $ty {
+                fn from_path_params(params: &[(String, String)]) -> HttpResult<Self> {
+                    match params {
+                        [param] => parse_param(param),
+                        _ => Err(HttpError::WrongPathParamCount(1, params.len())),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_path_params_for_value!(
+    bool, char, f32, f64, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, String
+);
+
+macro_rules! impl_from_path_params_for_tuple {
+    ($count:literal, $($ty:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<$($ty: FromStr),*> FromPathParams for ($($ty,)*) {
+            fn from_path_params(params: &[(String, String)]) -> HttpResult<Self> {
+                let [$($ty),*] = params else {
+                    return Err(HttpError::WrongPathParamCount($count, params.len()));
+                };
+                Ok(($(parse_param($ty)?,)*))
+            }
+        }
+    };
+}
+
+impl_from_path_params_for_tuple!(1, T1);
+impl_from_path_params_for_tuple!(2, T1, T2);
+impl_from_path_params_for_tuple!(3, T1, T2, T3);
+impl_from_path_params_for_tuple!(4, T1, T2, T3, T4);
+impl_from_path_params_for_tuple!(5, T1, T2, T3, T4, T5);
+impl_from_path_params_for_tuple!(6, T1, T2, T3, T4, T5, T6);
+
+impl FromPathParams for HashMap<String, String> {
+    fn from_path_params(params: &[(String, String)]) -> HttpResult<Self> {
+        Ok(params.iter().cloned().collect())
+    }
+}
+
+impl FromPathParams for Vec<(String, String)> {
+    fn from_path_params(params: &[(String, String)]) -> HttpResult<Self> {
+        Ok(params.to_vec())
+    }
+}
+
+#[cfg(feature = "serde")]
+pub use self::serde_extractors::{Json, Query};
+
+#[cfg(feature = "serde")]
+mod serde_extractors {
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use crate::{
+        error::HttpError,
+        header::{self, ContentType},
+        request::Request,
+        response::{BodyKind, IntoResponse, Response, ResponseBuilder},
+    };
+
+    use super::{FromRequest, FromRequestParts};
+
+    /// Deserializes the query of the request
+    #[derive(Debug, Clone)]
+    pub struct Query<T>(pub T);
+
+    impl<S: Clone, T: DeserializeOwned> FromRequestParts<S> for Query<T> {
+        type Rejection = HttpError;
+
+        fn from_request_parts(req: &Request<S>) -> Result<Self, Self::Rejection> {
+            serde_urlencoded::from_str(req.query_string())
+                .map(Query)
+                .map_err(|e| HttpError::InvalidQueryParams(e.to_string()))
+        }
+    }
+
+    /// Deserializes a JSON request body, or serializes a JSON response body
+    #[derive(Debug, Clone)]
+    pub struct Json<T>(pub T);
+
+    impl<S: Clone, T: DeserializeOwned> FromRequest<S> for Json<T> {
+        type Rejection = HttpError;
+
+        fn from_request(mut req: Request<S>) -> Result<Self, Self::Rejection> {
+            let mime_type = req
+                .typed_header::<ContentType>()
+                .map(|content_type| content_type.mime_type());
+            if !mime_type.is_some_and(|mime| mime == "application/json" || mime.ends_with("+json"))
+            {
+                let content_type = req
+                    .headers()
+                    .get(header::CONTENT_TYPE)
+                    .map(|content_type| content_type.to_string())
+                    .unwrap_or_default();
+                return Err(HttpError::UnexpectedContentType(content_type));
+            }
+
+            let Some(body) = req.bytes_body() else {
+                return Err(HttpError::MissingBytesBody);
+            };
+            serde_json::from_slice(&body)
+                .map(Json)
+                .map_err(|e| HttpError::InvalidJson(e.to_string()))
+        }
+    }
+
+    impl<T: Serialize> IntoResponse for Json<T> {
+        fn into_response(self) -> Response {
+            match serde_json::to_vec(&self.0) {
+                Ok(body) => ResponseBuilder::new()
+                    .with_bytes(body, BodyKind::Json)
+                    .build(),
+                Err(e) => HttpError::SerializeJsonError(e.to_string()).into_response(),
+            }
+        }
+    }
+}
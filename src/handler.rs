@@ -0,0 +1,138 @@
+use std::{future::Future, sync::Arc};
+
+use smol::future::Boxed;
+
+use crate::{
+    extract::{FromRequest, FromRequestParts},
+    request::Request,
+    response::{IntoResponse, Response},
+    router::HandlerFn,
+};
+
+/// A function or closure answering requests, either synchronously or asynchronously.
+///
+/// Handlers take up to 8 arguments. All of them but the last one implement
+/// [`FromRequestParts`], the last one implements [`FromRequest`] so it can consume the body. When
+/// an argument can't be extracted the rejection is sent back instead of calling the handler.
+///
+/// Since a single argument may be any extractor, the argument of a closure taking the whole
+/// request can't be inferred anymore and must be annotated: `|req: Request<S>| ...` instead of
+/// `|req| ...`. Functions are unaffected since their signature is always explicit.
+///
+/// Closures may capture their environment (configuration, channels, counters...) as long as it
+/// can be shared between the connections, i.e. is `Send + Sync + 'static`.
+///
+/// `T` only distinguishes the implementations from each other and is always inferred.
+pub trait Handler<T, S: Clone>: Send + Sync + 'static {
+    fn call(&self, req: Request<S>) -> Boxed<Response>;
+}
+
+/// Marks handlers directly returning a response
+#[doc(hidden)]
+pub struct IsSync;
+
+/// Marks handlers returning a future resolving to a response
+#[doc(hidden)]
+pub struct IsAsync;
+
+impl<F, E, S> Handler<(IsSync,), S> for F
+where
+    F: Fn() -> E + Send + Sync + 'static,
+    E: IntoResponse,
+    S: Clone,
+{
+    fn call(&self, _req: Request<S>) -> Boxed<Response> {
+        let res = self().into_response();
+        Box::pin(async move { res })
+    }
+}
+
+impl<F, Fut, S> Handler<(IsAsync,), S> for F
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
+    S: Clone,
+{
+    fn call(&self, _req: Request<S>) -> Boxed<Response> {
+        let fut = self();
+        Box::pin(async move { fut.await.into_response() })
+    }
+}
+
+/// Extracts the arguments of a handler from `$req`, returning the rejection if one of them fails
+macro_rules! extract {
+    ($req:ident, [$($ty:ident),*], $last:ident) => {
+        $(
+            let $ty = match $ty::from_request_parts(&$req) {
+                Ok(value) => value,
+                Err(rejection) => return Err(rejection.into_response()),
+            };
+        )*
+        let $last = match $last::from_request($req) {
+            Ok(value) => value,
+            Err(rejection) => return Err(rejection.into_response()),
+        };
+    };
+}
+
+macro_rules! impl_handler {
+    ([$($ty:ident),*], $last:ident) => {
+        #[allow(non_snake_case)]
+        impl<F, E, S, M, $($ty,)* $last> Handler<(IsSync, M, $($ty,)* $last), S> for F
+        where
+            F: Fn($($ty,)* $last) -> E + Send + Sync + 'static,
+            E: IntoResponse,
+            S: Clone,
+            $($ty: FromRequestParts<S>,)*
+            $last: FromRequest<S, M>,
+        {
+            fn call(&self, req: Request<S>) -> Boxed<Response> {
+                let extract = || {
+                    extract!(req, [$($ty),*], $last);
+                    Ok(self($($ty,)* $last))
+                };
+                let res = match extract() {
+                    Ok(res) => res.into_response(),
+                    Err(rejection) => rejection,
+                };
+                Box::pin(async move { res })
+            }
+        }
+
+        #[allow(non_snake_case)]
+        impl<F, Fut, S, M, $($ty,)* $last> Handler<(IsAsync, M, $($ty,)* $last), S> for F
+        where
+            F: Fn($($ty,)* $last) -> Fut + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: IntoResponse,
+            S: Clone,
+            $($ty: FromRequestParts<S>,)*
+            $last: FromRequest<S, M>,
+        {
+            fn call(&self, req: Request<S>) -> Boxed<Response> {
+                let extract = || {
+                    extract!(req, [$($ty),*], $last);
+                    Ok(self($($ty,)* $last))
+                };
+                match extract() {
+                    Ok(fut) => Box::pin(async move { fut.await.into_response() }),
+                    Err(rejection) => Box::pin(async move { rejection }),
+                }
+            }
+        }
+    };
+}
+
+impl_handler!([], T1);
+impl_handler!([T1], T2);
+impl_handler!([T1, T2], T3);
+impl_handler!([T1, T2, T3], T4);
+impl_handler!([T1, T2, T3, T4], T5);
+impl_handler!([T1, T2, T3, T4, T5], T6);
+impl_handler!([T1, T2, T3, T4, T5, T6], T7);
+impl_handler!([T1, T2, T3, T4, T5, T6, T7], T8);
+
+pub(crate) fn into_handler_fn<T, S: Clone, H: Handler<T, S>>(handler: H) -> HandlerFn<S> {
+    Arc::new(move |req| handler.call(req))
+}
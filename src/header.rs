@@ -1,9 +1,335 @@
-pub const CONTENT_TYPE: &str = "content-type";
-pub const CONTENT_LENGTH: &str = "content-length";
-pub const HOST: &str = "host";
-pub const USER_AGENT: &str = "user_agent";
-pub const ORIGIN: &str = "origin";
-pub const SEC_WEBSOCKET_VERSION: &str = "sec_websocket_version";
-pub const SEC_WEBSOCKET_KEY: &str = "sec-websocket-key";
-pub const SEC_WEBSOCKET_ACCEPT: &str = "sec-websocket-accept";
-pub const CONNECTION: &str = "connection";
+use std::borrow::Cow;
+
+use crate::error::{InvalidHeaderName, InvalidHeaderValue};
+
+mod typed;
+
+pub use typed::{
+    Accept, Authorization, ByteRange, CacheControl, CacheDirective, ContentLength, ContentType,
+    Date, ETag, MediaRange, Range, TypedHeader,
+};
+
+pub const ACCEPT: HeaderName = HeaderName::from_static("accept");
+pub const AUTHORIZATION: HeaderName = HeaderName::from_static("authorization");
+pub const CACHE_CONTROL: HeaderName = HeaderName::from_static("cache-control");
+pub const DATE: HeaderName = HeaderName::from_static("date");
+pub const ETAG: HeaderName = HeaderName::from_static("etag");
+pub const RANGE: HeaderName = HeaderName::from_static("range");
+pub const CONTENT_TYPE: HeaderName = HeaderName::from_static("content-type");
+pub const CONTENT_LENGTH: HeaderName = HeaderName::from_static("content-length");
+pub const TRANSFER_ENCODING: HeaderName = HeaderName::from_static("transfer-encoding");
+pub const HOST: HeaderName = HeaderName::from_static("host");
+pub const USER_AGENT: HeaderName = HeaderName::from_static("user-agent");
+pub const ORIGIN: HeaderName = HeaderName::from_static("origin");
+pub const SEC_WEBSOCKET_VERSION: HeaderName = HeaderName::from_static("sec-websocket-version");
+pub const SEC_WEBSOCKET_KEY: HeaderName = HeaderName::from_static("sec-websocket-key");
+pub const SEC_WEBSOCKET_ACCEPT: HeaderName = HeaderName::from_static("sec-websocket-accept");
+pub const SEC_WEBSOCKET_EXTENSIONS: HeaderName =
+    HeaderName::from_static("sec-websocket-extensions");
+pub const CONNECTION: HeaderName = HeaderName::from_static("connection");
+pub const ALLOW: HeaderName = HeaderName::from_static("allow");
+pub const UPGRADE: HeaderName = HeaderName::from_static("upgrade");
+
+/// Returns whether `c` can be part of a token, e.g. a header name or a method, as defined by
+/// RFC 9110
+pub(crate) const fn is_token_char(c: u8) -> bool {
+    matches!(c, b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'!' | b'#' | b'$' | b'%' | b'&' | b'\''
+        | b'*' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~')
+}
+
+/// A header name, which is case-insensitive and stored lowercase
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HeaderName(Cow<'static, str>);
+
+impl HeaderName {
+    /// Creates a header name from a lowercase literal.
+    ///
+    /// # Panics
+    ///
+    /// Panics, at compile time in constants, if `name` is not a valid lowercase header name.
+    pub const fn from_static(name: &'static str) -> Self {
+        let bytes = name.as_bytes();
+        assert!(!bytes.is_empty(), "Empty header name");
+        let mut i = 0;
+        while i < bytes.len() {
+            assert!(
+                is_token_char(bytes[i]) && !bytes[i].is_ascii_uppercase(),
+                "Header names must be lowercase tokens"
+            );
+            i += 1;
+        }
+        Self(Cow::Borrowed(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<&str> for HeaderName {
+    type Error = InvalidHeaderName;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        if name.is_empty() || !name.bytes().all(is_token_char) {
+            return Err(InvalidHeaderName(name.to_string()));
+        }
+        Ok(Self(Cow::Owned(name.to_ascii_lowercase())))
+    }
+}
+
+impl TryFrom<String> for HeaderName {
+    type Error = InvalidHeaderName;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Self::try_from(name.as_str())
+    }
+}
+
+impl AsRef<str> for HeaderName {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl std::fmt::Display for HeaderName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A header value, which can't contain control characters other than tabs so that it can't end
+/// the header line early, e.g. to inject another header
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HeaderValue(Cow<'static, str>);
+
+impl HeaderValue {
+    /// Creates a header value from a literal.
+    ///
+    /// # Panics
+    ///
+    /// Panics, at compile time in constants, if `value` contains a control character.
+    pub const fn from_static(value: &'static str) -> Self {
+        let bytes = value.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            assert!(is_value_char(bytes[i]), "Invalid header value");
+            i += 1;
+        }
+        Self(Cow::Borrowed(value))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+const fn is_value_char(c: u8) -> bool {
+    c == b'\t' || !c.is_ascii_control()
+}
+
+impl TryFrom<&str> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::try_from(value.to_string())
+    }
+}
+
+impl TryFrom<String> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if !value.bytes().all(is_value_char) {
+            return Err(InvalidHeaderValue(value));
+        }
+        Ok(Self(Cow::Owned(value)))
+    }
+}
+
+impl From<usize> for HeaderValue {
+    fn from(value: usize) -> Self {
+        Self(Cow::Owned(value.to_string()))
+    }
+}
+
+impl AsRef<str> for HeaderValue {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for HeaderValue {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for HeaderValue {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl std::fmt::Display for HeaderValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Headers of a request or a response, in the order they were added. A name can have several
+/// values, e.g. `Set-Cookie`, and is looked up case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMap {
+    entries: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the first value of `name`
+    pub fn get(&self, name: impl AsRef<str>) -> Option<&HeaderValue> {
+        self.get_all(name).next()
+    }
+
+    /// Returns every value of `name`, in order
+    pub fn get_all(&self, name: impl AsRef<str>) -> impl Iterator<Item = &HeaderValue> {
+        let name = name.as_ref().to_ascii_lowercase();
+        self.entries
+            .iter()
+            .filter(move |(entry, _)| entry.as_str() == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Parses the values of `H`, joined with commas if it is repeated
+    pub fn typed_get<H: TypedHeader>(&self) -> Option<H> {
+        let values: Vec<&str> = self.get_all(H::NAME).map(HeaderValue::as_str).collect();
+        if values.is_empty() {
+            return None;
+        }
+        H::parse(&values.join(", "))
+    }
+
+    pub fn contains_key(&self, name: impl AsRef<str>) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets `name` to `value`, replacing all its values and returning the first one
+    pub fn insert(&mut self, name: HeaderName, value: HeaderValue) -> Option<HeaderValue> {
+        match self.entries.iter().position(|(entry, _)| *entry == name) {
+            Some(i) => {
+                let previous = std::mem::replace(&mut self.entries[i].1, value);
+                // The other values are removed, the new one keeps the position of the first
+                let mut index = 0;
+                self.entries.retain(|(entry, _)| {
+                    let keep = index <= i || *entry != name;
+                    index += 1;
+                    keep
+                });
+                Some(previous)
+            }
+            None => {
+                self.entries.push((name, value));
+                None
+            }
+        }
+    }
+
+    /// Adds `value` to the values of `name`
+    pub fn append(&mut self, name: HeaderName, value: HeaderValue) {
+        self.entries.push((name, value));
+    }
+
+    /// Removes every value of `name`, returning the first one
+    pub fn remove(&mut self, name: impl AsRef<str>) -> Option<HeaderValue> {
+        let name = name.as_ref().to_ascii_lowercase();
+        let mut removed = None;
+        self.entries.retain(|(entry, value)| {
+            if entry.as_str() != name {
+                return true;
+            }
+            removed.get_or_insert_with(|| value.clone());
+            false
+        });
+        removed
+    }
+
+    /// Iterates over the headers in order, a name appears once per value
+    pub fn iter(&self) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+        self.entries.iter().map(|(name, value)| (name, value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HeaderMap, HeaderName, HeaderValue};
+
+    fn name(name: &str) -> HeaderName {
+        HeaderName::try_from(name).unwrap()
+    }
+
+    fn value(value: &str) -> HeaderValue {
+        HeaderValue::try_from(value).unwrap()
+    }
+
+    #[test]
+    fn multiple_values() {
+        let mut headers = HeaderMap::new();
+        headers.append(name("Set-Cookie"), value("a=1"));
+        headers.append(name("content-type"), value("text/plain"));
+        headers.append(name("set-cookie"), value("b=2"));
+
+        assert_eq!(headers.get("SET-COOKIE").unwrap(), "a=1");
+        let cookies: Vec<&str> = headers.get_all("set-cookie").map(|v| v.as_str()).collect();
+        assert_eq!(cookies, ["a=1", "b=2"]);
+
+        let names: Vec<&str> = headers.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["set-cookie", "content-type", "set-cookie"]);
+    }
+
+    #[test]
+    fn insert_replaces_values() {
+        let mut headers = HeaderMap::new();
+        headers.append(name("accept"), value("text/html"));
+        headers.append(name("host"), value("example.com"));
+        headers.append(name("accept"), value("application/json"));
+
+        let previous = headers.insert(name("Accept"), value("*/*"));
+        assert_eq!(previous.unwrap(), "text/html");
+        let headers: Vec<(&str, &str)> = headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(headers, [("accept", "*/*"), ("host", "example.com")]);
+    }
+
+    #[test]
+    fn remove() {
+        let mut headers = HeaderMap::new();
+        headers.append(name("via"), value("1.1 a"));
+        headers.append(name("via"), value("1.1 b"));
+        assert_eq!(headers.remove("Via").unwrap(), "1.1 a");
+        assert!(headers.is_empty());
+        assert!(headers.remove("via").is_none());
+    }
+
+    #[test]
+    fn invalid_names_and_values() {
+        for invalid in ["", "x header", "x:header", "x\r\nheader"] {
+            assert!(HeaderName::try_from(invalid).is_err(), "{invalid:?}");
+        }
+        for invalid in ["a\r\nSet-Cookie: b", "a\nb", "a\rb", "a\0b"] {
+            assert!(HeaderValue::try_from(invalid).is_err(), "{invalid:?}");
+        }
+        assert_eq!(value("a\tb c"), "a\tb c");
+    }
+}
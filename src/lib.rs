@@ -1,108 +1,1405 @@
+mod base64;
+pub mod body;
+pub mod config;
 pub mod error;
+pub mod extract;
+pub mod handler;
 pub mod header;
 pub mod http_version;
 pub mod method;
+pub mod middleware;
 pub mod request;
 pub mod response;
 pub mod route_path;
 pub mod router;
+mod sha1;
 pub mod status_code;
 pub mod ws;
 
-use std::{
+use std::sync::Arc;
+
+use smol::{
+    future,
+    io::{AsyncBufReadExt, BufReader},
     net::{TcpListener, TcpStream},
-    sync::Arc,
+    Timer,
 };
 
-use crate::{request::Request, response::IntoResponse};
+use crate::{
+    body::Body, config::ServerConfig, header::HeaderValue, http_version::HttpVersion,
+    method::Method, request::Request, response::IntoResponse, status_code::StatusCode,
+};
 
 use self::router::Router;
 
 pub type Result<T> = std::result::Result<T, error::Error>;
 pub type HttpResult<T> = std::result::Result<T, error::HttpError>;
 
-pub fn serve<S: Clone + Send + Sync + 'static>(
+/// Accepts connections on `listener` and answers their requests with `router`.
+///
+/// Every connection is driven by its own task on smol's global executor, so it must be awaited
+/// from an async context, e.g. with `smol::block_on`.
+pub async fn serve<S: Clone + Send + Sync + 'static>(
+    listener: TcpListener,
+    router: Router<S>,
+) -> Result<()> {
+    serve_with_config(listener, router, ServerConfig::default()).await
+}
+
+pub async fn serve_with_config<S: Clone + Send + Sync + 'static>(
     listener: TcpListener,
     router: Router<S>,
+    config: ServerConfig,
 ) -> Result<()> {
     let router = Arc::new(router);
-    let mut threads = Vec::new();
-    for stream in listener.incoming() {
-        let stream = match stream {
-            Ok(stream) => stream,
+    let config = Arc::new(config);
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
             Err(e) => return Err(error::Error::TcpStreamError(e)),
         };
-        let thread = smol::spawn(handle_client(stream, router.clone()));
-        threads.push(thread);
+        smol::spawn(handle_client(stream, router.clone(), config.clone())).detach();
     }
-
-    smol::block_on(async {
-        for thread in threads {
-            thread.detach();
-        }
-    });
-
-    Ok(())
 }
 
+/// Answers the requests of a connection in order until the client closes it, asks for it to be
+/// closed, stays idle for too long or reaches the maximum number of requests
 async fn handle_client<S: Clone + Send + Sync + 'static>(
-    mut stream: TcpStream,
+    stream: TcpStream,
     router: Arc<Router<S>>,
+    config: Arc<ServerConfig>,
 ) {
-    let req = match Request::parse(&mut stream, router.state().clone()) {
-        Ok(req) => req,
+    let peer_addr = match stream.peer_addr() {
+        Ok(addr) => addr,
         Err(e) => {
-            e.into_response().send_to_stream(&mut stream);
+            error::HttpError::GetPeerAddrError(e).log();
             return;
         }
     };
 
-    let mut res = router.handle(req);
+    let mut buf = BufReader::new(stream);
+    let mut handled_requests = 0;
 
-    res.send_to_stream(&mut stream);
+    loop {
+        // Wait for the next request, an empty buffer means the client closed the connection
+        let has_request = future::or(
+            async {
+                match buf.fill_buf().await {
+                    Ok(bytes) => !bytes.is_empty(),
+                    Err(_) => false,
+                }
+            },
+            async {
+                Timer::after(config.idle_timeout).await;
+                false
+            },
+        )
+        .await;
+        if !has_request {
+            return;
+        }
+
+        // A client starting a request and then stalling would otherwise hold the connection
+        let parsed = future::or(
+            async {
+                let state = router.state().clone();
+                let max_body_size = config.max_body_size;
+                Some(
+                    Request::parse_with_max_body_size(&mut buf, peer_addr, state, max_body_size)
+                        .await,
+                )
+            },
+            async {
+                Timer::after(config.request_timeout).await;
+                None
+            },
+        )
+        .await;
+        let req = match parsed.unwrap_or(Err(error::HttpError::RequestTimeout)) {
+            Ok(req) => req,
+            Err(e) => {
+                // The rest of the stream can't be trusted anymore
+                let mut res = e.into_response();
+                res.headers
+                    .insert(header::CONNECTION, HeaderValue::from_static("close"));
+                let _ = res.send_to_stream(buf.get_mut()).await;
+                return;
+            }
+        };
+        handled_requests += 1;
+
+        let mut keep_alive =
+            req.keep_alive() && handled_requests < config.max_requests_per_connection;
+        let head = *req.method() == Method::Head;
+        let http_version = *req.http_version();
+
+        let mut res = router.handle(req).await;
+        res.http_version = http_version;
+        if let Some(upgrade) = res.upgrade.take() {
+            if res.status_code == StatusCode::SwitchingProtocols {
+                if res.send_to_stream(buf.get_mut()).await.is_ok() {
+                    upgrade.run(buf, config.max_websocket_message_size).await;
+                }
+                return;
+            }
+            // A layer replaced the 101, the response is sent as usual and the connection stays HTTP
+            res.headers.remove(header::UPGRADE);
+            res.headers.remove(header::CONNECTION);
+        }
+        // A streamed body is ended by closing the connection with HTTP/1.0
+        if http_version == HttpVersion::HTTP1_0 && matches!(res.body, Body::Stream(_)) && !head {
+            keep_alive = false;
+        }
+        if !keep_alive {
+            res.headers
+                .insert(header::CONNECTION, HeaderValue::from_static("close"));
+        } else if http_version == HttpVersion::HTTP1_0 {
+            res.headers
+                .insert(header::CONNECTION, HeaderValue::from_static("keep-alive"));
+        }
+
+        let sent = if head {
+            res.send_head_to_stream(buf.get_mut()).await
+        } else {
+            res.send_to_stream(buf.get_mut()).await
+        };
+        if sent.is_err() || !keep_alive {
+            return;
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::{
-        io::{BufRead, BufReader, Write},
-        net::{TcpListener, TcpStream},
+        io::{BufRead, BufReader, Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
         thread,
+        time::Duration,
     };
 
-    use crate::router::Router;
+    use crate::{
+        config::ServerConfig,
+        header::{HeaderName, HeaderValue},
+        method::Method,
+        middleware::Next,
+        request::Request,
+        response::{BodyKind, IntoResponse, ResponseBuilder},
+        router::Router,
+        status_code::StatusCode,
+        ws::Message,
+    };
 
     #[test]
     fn test_app() {
-        let router = Router::new().get("/", move |req| "slt");
+        let router = Router::new().get("/", move |_req: Request<()>| "slt");
 
         let listener = TcpListener::bind("0.0.0.0:8080").unwrap();
 
         thread::spawn(move || {
-            crate::serve(listener, router).unwrap();
+            smol::block_on(crate::serve(listener.try_into().unwrap(), router)).unwrap();
         });
 
         let mut connection = TcpStream::connect("127.0.0.1:8080").unwrap();
-        let req = r#"GET / HTTP/1.1"#;
-        connection.write(req.as_bytes()).unwrap();
+        let req = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        connection.write_all(req.as_bytes()).unwrap();
         let mut buf = BufReader::new(&mut connection);
         let mut result = String::new();
         buf.read_line(&mut result).unwrap();
         println!("{result}");
         assert_eq!(r#"HTTP/1.1 200 OK"#, result.trim());
 
-        let req = r#"GET /a HTTP/1.1"#;
-        connection.write(req.as_bytes()).unwrap();
+        let req = "GET /a HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        connection.write_all(req.as_bytes()).unwrap();
         let mut buf = BufReader::new(&mut connection);
         let mut result = String::new();
         buf.read_line(&mut result).unwrap();
-        assert_eq!(r#"HTTP/1.1 404 NOT FOUND"#, result.trim());
+        assert_eq!(r#"HTTP/1.1 404 Not Found"#, result.trim());
 
-        let req = r#"POST / HTTP/1.1"#;
-        connection.write(req.as_bytes()).unwrap();
+        let req = "POST / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        connection.write_all(req.as_bytes()).unwrap();
         let mut buf = BufReader::new(&mut connection);
         let mut result = String::new();
         buf.read_line(&mut result).unwrap();
-        assert_eq!(r#"HTTP/1.1 405 METHOD NOT ALLOWED"#, result.trim());
+        assert_eq!(r#"HTTP/1.1 405 Method Not Allowed"#, result.trim());
+    }
+
+    /// Starts a server on a random port and returns its address
+    fn spawn_server<S: Clone + Send + Sync + 'static>(
+        router: Router<S>,
+        config: ServerConfig,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let listener = listener.try_into().unwrap();
+            smol::block_on(crate::serve_with_config(listener, router, config)).unwrap();
+        });
+        addr
+    }
+
+    /// Sends `raw` on a new connection and returns the status line, headers with lowercase names
+    /// and body of the response
+    fn send(addr: SocketAddr, raw: impl AsRef<[u8]>) -> (String, Vec<(String, String)>, String) {
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection.write_all(raw.as_ref()).unwrap();
+        read_full_response(&mut BufReader::new(connection))
+    }
+
+    /// Reads a response and returns its status line and body
+    fn read_response(buf: &mut impl BufRead) -> (String, String) {
+        let (status, _, body) = read_full_response(buf);
+        (status, body)
+    }
+
+    /// Reads a response and returns its status line, headers with lowercase names and body
+    fn read_full_response(buf: &mut impl BufRead) -> (String, Vec<(String, String)>, String) {
+        let (status, headers) = read_response_head(buf);
+        let content_length = headers
+            .iter()
+            .find(|(name, _)| name == "content-length")
+            .map_or(0, |(_, value)| value.parse().unwrap());
+        let mut body = vec![0; content_length];
+        buf.read_exact(&mut body).unwrap();
+        (status, headers, String::from_utf8(body).unwrap())
+    }
+
+    /// Reads the status line and headers of a response, with lowercase names, but not its body
+    fn read_response_head(buf: &mut impl BufRead) -> (String, Vec<(String, String)>) {
+        let mut status = String::new();
+        buf.read_line(&mut status).unwrap();
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            buf.read_line(&mut line).unwrap();
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(": ") {
+                headers.push((name.to_lowercase(), value.to_string()));
+            }
+        }
+        (status.trim().to_string(), headers)
+    }
+
+    #[test]
+    fn pipelined_requests() {
+        let router = Router::new()
+            .get("/a", |_req: Request<()>| "a")
+            .get("/b", |_req: Request<()>| "b");
+        let addr = spawn_server(router, ServerConfig::default());
+
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection
+            .write_all(b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\nGET /b HTTP/1.1\r\nHost: localhost\r\n\r\nGET /a HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut buf = BufReader::new(connection);
+        assert_eq!(read_response(&mut buf).1, "a");
+        assert_eq!(read_response(&mut buf).1, "b");
+        assert_eq!(read_response(&mut buf).1, "a");
+    }
+
+    #[test]
+    fn connection_close() {
+        let router = Router::new().get("/", |_req: Request<()>| "slt");
+        let addr = spawn_server(router, ServerConfig::default());
+
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut buf = BufReader::new(connection);
+        assert_eq!(read_response(&mut buf).0, "HTTP/1.1 200 OK");
+        let mut rest = String::new();
+        assert_eq!(buf.read_to_string(&mut rest).unwrap(), 0);
+    }
+
+    #[test]
+    fn max_requests_per_connection() {
+        let router = Router::new().get("/", |_req: Request<()>| "slt");
+        let config = ServerConfig::new().with_max_requests_per_connection(2);
+        let addr = spawn_server(router, config);
+
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut buf = BufReader::new(connection);
+        read_response(&mut buf);
+        read_response(&mut buf);
+        let mut rest = String::new();
+        buf.read_to_string(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn max_body_size() {
+        let router = Router::new().post("/", |body: String| body);
+        let addr = spawn_server(router, ServerConfig::new().with_max_body_size(4));
+
+        for (req, status) in [
+            ("Content-Length: 4\r\n\r\nabcd", "HTTP/1.1 200 OK"),
+            (
+                "Content-Length: 5\r\n\r\nabcde",
+                "HTTP/1.1 413 Content Too Large",
+            ),
+            (
+                "Transfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+                "HTTP/1.1 413 Content Too Large",
+            ),
+        ] {
+            let req =
+                format!("POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\n{req}");
+            assert_eq!(send(addr, &req).0, status, "{req}");
+        }
+    }
+
+    #[test]
+    fn request_timeout() {
+        let router = Router::new().post("/", |body: String| body);
+        let config = ServerConfig::new().with_request_timeout(Duration::from_millis(100));
+        let addr = spawn_server(router, config);
+
+        for req in [
+            "G",
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nab",
+        ] {
+            let mut connection = TcpStream::connect(addr).unwrap();
+            connection.write_all(req.as_bytes()).unwrap();
+            let mut buf = BufReader::new(connection);
+            let (status, headers, _) = read_full_response(&mut buf);
+            assert_eq!(status, "HTTP/1.1 408 Request Timeout", "{req}");
+            assert_eq!(header(&headers, "connection"), Some("close"), "{req}");
+            assert_eq!(buf.read(&mut [0; 1]).unwrap(), 0, "{req}");
+        }
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let router = Router::new().get("/", |_req: Request<()>| "slt");
+        let config = ServerConfig::new().with_idle_timeout(Duration::from_millis(100));
+        let addr = spawn_server(router, config);
+
+        let connection = TcpStream::connect(addr).unwrap();
+        connection
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = BufReader::new(connection);
+        let mut rest = String::new();
+        assert_eq!(buf.read_to_string(&mut rest).unwrap(), 0);
+    }
+
+    #[test]
+    fn slow_client_does_not_stall_others() {
+        let router = Router::new().get("/", |_req: Request<()>| "slt");
+        let addr = spawn_server(router, ServerConfig::default());
+
+        // Sends half of a request and never finishes it
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.write_all(b"GET / HTTP/1.1\r\nHost: ").unwrap();
+
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        connection
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut buf = BufReader::new(connection);
+        assert_eq!(
+            read_response(&mut buf),
+            ("HTTP/1.1 200 OK".to_string(), "slt".to_string())
+        );
+    }
+
+    async fn async_handler(req: Request<()>) -> String {
+        smol::Timer::after(Duration::from_millis(10)).await;
+        format!("Hello {}", req.param("name").unwrap())
+    }
+
+    #[test]
+    fn async_handlers() {
+        let router = Router::new()
+            .get("/hello/:name", async_handler)
+            .get("/sync", |_req: Request<()>| "sync")
+            .get("/closure", |_req: Request<()>| async { "async closure" });
+        let addr = spawn_server(router, ServerConfig::default());
+
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection
+            .write_all(b"GET /hello/world HTTP/1.1\r\nHost: localhost\r\n\r\nGET /sync HTTP/1.1\r\nHost: localhost\r\n\r\nGET /closure HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut buf = BufReader::new(connection);
+        assert_eq!(read_response(&mut buf).1, "Hello world");
+        assert_eq!(read_response(&mut buf).1, "sync");
+        assert_eq!(read_response(&mut buf).1, "async closure");
+    }
+
+    #[test]
+    fn capturing_closures() {
+        let greeting = String::from("Hello");
+        let counter = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+
+        let router = Router::new()
+            .get("/greet", move |_req: Request<()>| greeting.clone())
+            .get("/count", {
+                let counter = counter.clone();
+                move |_req: Request<()>| (counter.fetch_add(1, Ordering::SeqCst) + 1).to_string()
+            })
+            .post("/notify", move |_req: Request<()>| {
+                sender.send("notified").unwrap();
+                "ok"
+            });
+        let addr = spawn_server(router, ServerConfig::default());
+
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection
+            .write_all(b"GET /greet HTTP/1.1\r\nHost: localhost\r\n\r\nGET /count HTTP/1.1\r\nHost: localhost\r\n\r\nGET /count HTTP/1.1\r\nHost: localhost\r\n\r\nPOST /notify HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut buf = BufReader::new(connection);
+        assert_eq!(read_response(&mut buf).1, "Hello");
+        assert_eq!(read_response(&mut buf).1, "1");
+        assert_eq!(read_response(&mut buf).1, "2");
+        assert_eq!(read_response(&mut buf).1, "ok");
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+        assert_eq!(receiver.recv().unwrap(), "notified");
+    }
+
+    #[test]
+    fn malformed_requests() {
+        let router = Router::new().get("/", |_req: Request<()>| "slt");
+        let addr = spawn_server(router, ServerConfig::default());
+
+        for (req, status) in [
+            (&b"garbage\r\n\r\n"[..], "HTTP/1.1 400 Bad Request"),
+            (
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nno colon\r\n\r\n",
+                "HTTP/1.1 400 Bad Request",
+            ),
+            (b"GET / HTTP/1.1\r\n\r\n", "HTTP/1.1 400 Bad Request"),
+            (
+                b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\nContent-Length: +5\r\n\r\nhello",
+                "HTTP/1.1 400 Bad Request",
+            ),
+            (
+                b"BREW / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 501 Not Implemented",
+            ),
+            (
+                b"GET / HTTP/3\r\n\r\n",
+                "HTTP/1.1 505 HTTP Version Not Supported",
+            ),
+        ] {
+            assert_eq!(send(addr, req).0, status);
+        }
+
+        // The server is still up
+        assert_eq!(
+            send(addr, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").2,
+            "slt"
+        );
+    }
+
+    #[test]
+    fn middleware() {
+        let router = Router::new()
+            .get("/public", || "public")
+            .layer(|req: Request<()>, next: Next<()>| async move {
+                let mut res = next.run(req).await;
+                res.headers.insert(
+                    HeaderName::from_static("x-layer"),
+                    HeaderValue::from_static("outer"),
+                );
+                res
+            })
+            .get("/private", |req: Request<()>| {
+                req.headers()
+                    .get("x-user")
+                    .map(|user| user.to_string())
+                    .unwrap_or_default()
+            })
+            .route_layer(|mut req: Request<()>, next: Next<()>| async move {
+                let Some(token) = req.headers().get("authorization").cloned() else {
+                    return StatusCode::Forbidden.into_response();
+                };
+                req.headers_mut()
+                    .insert(HeaderName::from_static("x-user"), token);
+                next.run(req).await
+            });
+        let addr = spawn_server(router, ServerConfig::default());
+
+        // `route_layer` is added last so it runs first and short-circuits `layer`, which doesn't
+        // wrap `/private` as it is added after it
+        for (req, status, body, layered) in [
+            (
+                "GET /public HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 403 Forbidden",
+                "",
+                false,
+            ),
+            (
+                "GET /public HTTP/1.1\r\nHost: localhost\r\nAuthorization: alice\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "public",
+                true,
+            ),
+            (
+                "GET /private HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 403 Forbidden",
+                "",
+                false,
+            ),
+            (
+                "GET /private HTTP/1.1\r\nHost: localhost\r\nAuthorization: bob\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "bob",
+                false,
+            ),
+            // Only `layer` runs when no route matches
+            (
+                "GET /unknown HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 404 Not Found",
+                "",
+                true,
+            ),
+        ] {
+            let (status_line, headers, res_body) = send(addr, req);
+            assert_eq!(status_line, status, "{req}");
+            assert_eq!(res_body, body, "{req}");
+            let has_header = headers
+                .iter()
+                .any(|(name, value)| name == "x-layer" && value == "outer");
+            assert_eq!(has_header, layered, "{req}");
+        }
+    }
+
+    #[test]
+    fn nested_routers() {
+        let users = Router::new()
+            .get("/", || "users")
+            .get("/:id", |req: Request<()>| {
+                req.param("id").unwrap_or_default().to_string()
+            })
+            .layer(|req: Request<()>, next: Next<()>| async move {
+                let mut res = next.run(req).await;
+                res.headers.insert(
+                    HeaderName::from_static("x-layer"),
+                    HeaderValue::from_static("users"),
+                );
+                res
+            });
+        let health = Router::new().get("/health", || "ok");
+        let router = Router::new()
+            .get("/", || "root")
+            .nest("/api/users/", users)
+            .merge(health);
+        let addr = spawn_server(router, ServerConfig::default());
+
+        for (req, status, body, layered) in [
+            (
+                "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "root",
+                false,
+            ),
+            (
+                "GET /api/users HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "users",
+                true,
+            ),
+            (
+                "GET /api/users/42 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "42",
+                true,
+            ),
+            (
+                "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "ok",
+                false,
+            ),
+            (
+                "GET /42 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 404 Not Found",
+                "",
+                false,
+            ),
+        ] {
+            let (status_line, headers, res_body) = send(addr, req);
+            assert_eq!(status_line, status, "{req}");
+            assert_eq!(res_body, body, "{req}");
+            let has_header = headers
+                .iter()
+                .any(|(name, value)| name == "x-layer" && value == "users");
+            assert_eq!(has_header, layered, "{req}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "A handler is already registered for GET /api/:id")]
+    fn nested_route_conflict() {
+        let api = Router::new().get("/{id}", || "nested");
+        let _ = Router::new().get("/api/:id", || "root").nest("/api", api);
+    }
+
+    #[test]
+    fn fallbacks() {
+        let api = Router::new().get("/users", || "users").fallback(|| {
+            ResponseBuilder::new()
+                .with_status_code(StatusCode::NotFound)
+                .with_body(r#"{"error":"not found"}"#, BodyKind::Json)
+                .build()
+        });
+        let router = Router::new()
+            .get("/", || "home")
+            .nest("/api", api)
+            .fallback(|req: Request<()>| format!("index.html for {}", req.uri()));
+        let addr = spawn_server(router, ServerConfig::default());
+
+        for (req, status, body) in [
+            (
+                "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "home",
+            ),
+            (
+                "GET /settings/profile HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "index.html for /settings/profile",
+            ),
+            (
+                "GET /api/users HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "users",
+            ),
+            (
+                "GET /api/posts HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 404 Not Found",
+                r#"{"error":"not found"}"#,
+            ),
+            (
+                "GET /apiv2 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "index.html for /apiv2",
+            ),
+            // Known paths with another method are not handled by the fallback
+            (
+                "POST / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 405 Method Not Allowed",
+                "",
+            ),
+        ] {
+            let (status_line, _, res_body) = send(addr, req);
+            assert_eq!(status_line, status, "{req}");
+            assert_eq!(res_body, body, "{req}");
+        }
+    }
+
+    #[test]
+    fn fallback_layers() {
+        let layer = |value: &'static str| {
+            move |req: Request<()>, next: Next<()>| async move {
+                let mut res = next.run(req).await;
+                res.headers.append(
+                    HeaderName::from_static("x-layer"),
+                    HeaderValue::from_static(value),
+                );
+                res
+            }
+        };
+        let inner = Router::new()
+            .fallback(|| "inner fallback")
+            .layer(layer("inner"));
+        let middle = Router::new().nest("/inner", inner).layer(layer("middle"));
+        let merged = Router::new()
+            .get("/merged", || "merged")
+            .fallback(|| "merged fallback")
+            .layer(layer("merged"));
+        let router = Router::new().nest("/middle", middle).merge(merged);
+        let addr = spawn_server(router, ServerConfig::default());
+
+        for (req, body, layers) in [
+            (
+                "GET /middle/inner/unknown HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "inner fallback",
+                vec!["inner", "middle"],
+            ),
+            (
+                "GET /unknown HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "merged fallback",
+                vec!["merged"],
+            ),
+        ] {
+            let (_, headers, res_body) = send(addr, req);
+            assert_eq!(res_body, body, "{req}");
+            let applied: Vec<&str> = headers
+                .iter()
+                .filter(|(name, _)| name == "x-layer")
+                .map(|(_, value)| value.as_str())
+                .collect();
+            assert_eq!(applied, layers, "{req}");
+        }
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn head_requests() {
+        let router = Router::new()
+            .get("/text", || "hello")
+            .get("/custom", || "get")
+            .head("/custom", || {
+                ResponseBuilder::new()
+                    .append_header("x-head", "custom")
+                    .build()
+            })
+            .post("/form", || "posted");
+        let addr = spawn_server(router, ServerConfig::default());
+
+        let mut connection = TcpStream::connect(addr).unwrap();
+        // The body would be read as the next response if it were sent
+        connection
+            .write_all(b"HEAD /text HTTP/1.1\r\nHost: localhost\r\n\r\nGET /text HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut buf = BufReader::new(connection);
+        let (status, headers) = read_response_head(&mut buf);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(header(&headers, "content-length"), Some("5"));
+        assert_eq!(header(&headers, "content-type"), Some("text/plain"));
+        assert_eq!(
+            read_response(&mut buf),
+            ("HTTP/1.1 200 OK".to_string(), "hello".to_string())
+        );
+
+        for (req, status, custom) in [
+            (
+                "HEAD /custom HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                true,
+            ),
+            (
+                "HEAD /form HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 405 Method Not Allowed",
+                false,
+            ),
+            (
+                "HEAD /unknown HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 404 Not Found",
+                false,
+            ),
+        ] {
+            let (status_line, headers, _) = send(addr, req);
+            assert_eq!(status_line, status, "{req}");
+            assert_eq!(header(&headers, "x-head").is_some(), custom, "{req}");
+        }
+    }
+
+    #[test]
+    fn percent_encoded_params() {
+        let router = Router::new()
+            .get("/users/:name", |req: Request<()>| {
+                req.param("name").unwrap_or_default().to_string()
+            })
+            .get("/files/*path", |req: Request<()>| {
+                req.param("path").unwrap_or_default().to_string()
+            });
+        let addr = spawn_server(router, ServerConfig::default());
+
+        for (req, status, body) in [
+            (
+                "GET /users/John%20Doe HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "John Doe",
+            ),
+            (
+                "GET /files/docs/my%20doc.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "docs/my doc.txt",
+            ),
+            // Not UTF-8 once decoded
+            (
+                "GET /users/%FF HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 400 Bad Request",
+                "",
+            ),
+        ] {
+            let (status_line, _, res_body) = send(addr, req);
+            assert_eq!(status_line, status, "{req}");
+            assert_eq!(res_body, body, "{req}");
+        }
+    }
+
+    #[test]
+    fn method_mismatch_backtracks() {
+        let router = Router::new()
+            .get("/users/me", || "me")
+            .post("/users/:id", |req: Request<()>| {
+                format!("posted {}", req.param("id").unwrap())
+            });
+        let addr = spawn_server(router, ServerConfig::default());
+
+        for (req, status, allow, body) in [
+            (
+                "GET /users/me HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                None,
+                "me",
+            ),
+            // The static route has no POST handler, the dynamic one does
+            (
+                "POST /users/me HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                None,
+                "posted me",
+            ),
+            (
+                "POST /users/42 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                None,
+                "posted 42",
+            ),
+            // Allow lists the methods of every matching route
+            (
+                "PUT /users/me HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 405 Method Not Allowed",
+                Some("GET, HEAD, OPTIONS, POST"),
+                "",
+            ),
+            (
+                "GET /users/42 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 405 Method Not Allowed",
+                Some("OPTIONS, POST"),
+                "",
+            ),
+        ] {
+            let (status_line, headers, res_body) = send(addr, req);
+            assert_eq!(status_line, status, "{req}");
+            assert_eq!(header(&headers, "allow"), allow, "{req}");
+            assert_eq!(res_body, body, "{req}");
+        }
+    }
+
+    #[test]
+    fn options_requests() {
+        let router = Router::new()
+            .get("/users", || "users")
+            .post("/users", || "created")
+            .delete("/users/:id", || "deleted")
+            .options("/custom", || "custom options");
+        let addr = spawn_server(router, ServerConfig::default());
+
+        for (req, status, allow, body) in [
+            (
+                "OPTIONS /users HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                Some("GET, HEAD, OPTIONS, POST"),
+                "",
+            ),
+            (
+                "OPTIONS /users/1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                Some("DELETE, OPTIONS"),
+                "",
+            ),
+            (
+                "PUT /users/1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 405 Method Not Allowed",
+                Some("DELETE, OPTIONS"),
+                "",
+            ),
+            (
+                "OPTIONS /custom HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                None,
+                "custom options",
+            ),
+            (
+                "OPTIONS /unknown HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 404 Not Found",
+                None,
+                "",
+            ),
+        ] {
+            let (status_line, headers, res_body) = send(addr, req);
+            assert_eq!(status_line, status, "{req}");
+            assert_eq!(header(&headers, "allow"), allow, "{req}");
+            assert_eq!(res_body, body, "{req}");
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_insert() {
+        let router = Router::new().insert(Method::Get, "/", |_req: Request<()>| "slt");
+        let addr = spawn_server(router, ServerConfig::default());
+
+        assert_eq!(
+            send(addr, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").2,
+            "slt"
+        );
+    }
+
+    #[test]
+    fn extension_methods() {
+        let propfind = Method::Extension("PROPFIND".to_string());
+        let router =
+            Router::new()
+                .get("/", || "slt")
+                .route(propfind, "/files/*path", |req: Request<()>| {
+                    format!("properties of {}", req.param("path").unwrap_or_default())
+                });
+        let addr = spawn_server(router, ServerConfig::default());
+
+        for (req, status, body) in [
+            (
+                "PROPFIND /files/a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 200 OK",
+                "properties of a.txt",
+            ),
+            (
+                "PROPFIND / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 405 Method Not Allowed",
+                "",
+            ),
+            (
+                "MKCOL /files/dir HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 501 Not Implemented",
+                "",
+            ),
+            (
+                "get / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 501 Not Implemented",
+                "",
+            ),
+        ] {
+            let (status_line, _, res_body) = send(addr, req);
+            assert_eq!(status_line, status, "{req}");
+            assert_eq!(res_body, body, "{req}");
+        }
+    }
+
+    #[test]
+    fn http_1_0() {
+        let router = Router::new().get("/", || "slt").get("/stream", || {
+            ResponseBuilder::new()
+                .with_chunks(["a", "b", "c"], BodyKind::Text)
+                .build()
+        });
+        let addr = spawn_server(router, ServerConfig::default());
+
+        // The connection is closed after the response by default
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let mut buf = BufReader::new(connection);
+        let (status, headers, body) = read_full_response(&mut buf);
+        assert_eq!(status, "HTTP/1.0 200 OK");
+        assert_eq!(header(&headers, "connection"), Some("close"));
+        assert_eq!(body, "slt");
+        assert_eq!(buf.read(&mut [0; 1]).unwrap(), 0);
+
+        // Unless the client asks for it to stay open
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection
+            .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let mut buf = BufReader::new(connection);
+        let (status, headers, _) = read_full_response(&mut buf);
+        assert_eq!(status, "HTTP/1.0 200 OK");
+        assert_eq!(header(&headers, "connection"), Some("keep-alive"));
+        buf.get_mut().write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(
+            read_response(&mut buf),
+            ("HTTP/1.0 200 OK".to_string(), "slt".to_string())
+        );
+
+        // Streamed bodies are not chunked and end with the connection
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection
+            .write_all(b"GET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let mut buf = BufReader::new(connection);
+        let (status, headers) = read_response_head(&mut buf);
+        assert_eq!(status, "HTTP/1.0 200 OK");
+        assert_eq!(header(&headers, "transfer-encoding"), None);
+        assert_eq!(header(&headers, "connection"), Some("close"));
+        let mut body = String::new();
+        buf.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "abc");
+    }
+
+    const WEBSOCKET_HANDSHAKE: &str = "GET /echo/bob HTTP/1.1\r\nHost: localhost\r\n\
+        Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\n\r\n";
+
+    /// Encodes a frame masked like a client must
+    fn client_frame(head: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![head, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    /// Reads a short unmasked frame and returns its first byte and payload
+    fn read_frame(buf: &mut impl BufRead) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        buf.read_exact(&mut head).unwrap();
+        let mut payload = vec![0; head[1] as usize];
+        buf.read_exact(&mut payload).unwrap();
+        (head[0], payload)
+    }
+
+    fn echo_router() -> Router<()> {
+        Router::new().ws("/echo/:name", |req: Request<()>, mut ws| async move {
+            let greeting = format!("hello {}", req.param("name").unwrap_or_default());
+            ws.send(Message::Text(greeting)).await.unwrap();
+            loop {
+                match ws.recv().await {
+                    Ok(message @ (Message::Text(_) | Message::Binary(_))) => {
+                        ws.send(message).await.unwrap()
+                    }
+                    Ok(Message::Ping(_) | Message::Pong(_)) => {}
+                    Ok(Message::Close(_)) | Err(_) => break,
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn websocket_upgrade() {
+        let addr = spawn_server(echo_router(), ServerConfig::default());
+
+        let mut connection = TcpStream::connect(addr).unwrap();
+        // The first frame is sent along with the handshake
+        let mut handshake = WEBSOCKET_HANDSHAKE.as_bytes().to_vec();
+        handshake.extend(client_frame(0x01, b"Hel"));
+        connection.write_all(&handshake).unwrap();
+        let mut buf = BufReader::new(connection.try_clone().unwrap());
+        let (status, headers) = read_response_head(&mut buf);
+        assert_eq!(status, "HTTP/1.1 101 Switching Protocols");
+        assert_eq!(
+            header(&headers, "sec-websocket-accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+        assert_eq!(header(&headers, "content-length"), None);
+        assert_eq!(read_frame(&mut buf), (0x81, b"hello bob".to_vec()));
+
+        // A ping can come between the fragments of a message
+        connection.write_all(&client_frame(0x89, b"p")).unwrap();
+        connection.write_all(&client_frame(0x80, b"lo")).unwrap();
+        assert_eq!(read_frame(&mut buf), (0x8A, b"p".to_vec()));
+        assert_eq!(read_frame(&mut buf), (0x81, b"Hello".to_vec()));
+
+        connection
+            .write_all(&client_frame(0x82, &[1, 2, 3]))
+            .unwrap();
+        assert_eq!(read_frame(&mut buf), (0x82, vec![1, 2, 3]));
+
+        // The close code is echoed, then the server closes the connection
+        connection
+            .write_all(&client_frame(0x88, b"\x03\xE8bye"))
+            .unwrap();
+        assert_eq!(read_frame(&mut buf), (0x88, vec![0x03, 0xE8]));
+        assert_eq!(buf.read(&mut [0; 1]).unwrap(), 0);
+
+        for (req, status, version) in [
+            (
+                "GET /echo/bob HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 400 Bad Request",
+                None,
+            ),
+            (
+                "GET /echo/bob HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                 Sec-WebSocket-Version: 7\r\n\r\n",
+                "HTTP/1.1 426 Upgrade Required",
+                Some("13"),
+            ),
+        ] {
+            let (status_line, headers, _) = send(addr, req);
+            assert_eq!(status_line, status, "{req}");
+            assert_eq!(header(&headers, "sec-websocket-version"), version, "{req}");
+        }
+    }
+
+    #[test]
+    fn websocket_split() {
+        let router = Router::new().ws("/echo/:name", |_req: Request<()>, ws| async move {
+            let (mut reader, writer) = ws.split();
+            // Sent while the reader waits for the first message
+            let greeting = smol::spawn({
+                let writer = writer.clone();
+                async move { writer.send(Message::Text("hello".to_string())).await }
+            });
+            while let Ok(Message::Text(text)) = reader.recv().await {
+                writer
+                    .send(Message::Text(text.to_uppercase()))
+                    .await
+                    .unwrap();
+            }
+            greeting.await.unwrap();
+            // The close received by the reader also closes the writer
+            assert!(writer
+                .send(Message::Text("late".to_string()))
+                .await
+                .is_err());
+        });
+        let addr = spawn_server(router, ServerConfig::default());
+
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection
+            .write_all(WEBSOCKET_HANDSHAKE.as_bytes())
+            .unwrap();
+        let mut buf = BufReader::new(connection.try_clone().unwrap());
+        assert_eq!(
+            read_response_head(&mut buf).0,
+            "HTTP/1.1 101 Switching Protocols"
+        );
+        assert_eq!(read_frame(&mut buf), (0x81, b"hello".to_vec()));
+
+        connection.write_all(&client_frame(0x81, b"abc")).unwrap();
+        assert_eq!(read_frame(&mut buf), (0x81, b"ABC".to_vec()));
+        connection
+            .write_all(&client_frame(0x88, b"\x03\xE8"))
+            .unwrap();
+        assert_eq!(read_frame(&mut buf), (0x88, vec![0x03, 0xE8]));
+        assert_eq!(buf.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn websocket_upgrade_replaced_by_layer() {
+        let router = echo_router().layer(|req: Request<()>, next: Next<()>| async move {
+            let mut res = next.run(req).await;
+            res.status_code = StatusCode::Forbidden;
+            res
+        });
+        let addr = spawn_server(router, ServerConfig::default());
+
+        let mut connection = TcpStream::connect(addr).unwrap();
+        connection
+            .write_all(WEBSOCKET_HANDSHAKE.as_bytes())
+            .unwrap();
+        let mut buf = BufReader::new(connection.try_clone().unwrap());
+        let (status, headers, _) = read_full_response(&mut buf);
+        assert_eq!(status, "HTTP/1.1 403 Forbidden");
+        assert_eq!(header(&headers, "upgrade"), None);
+        assert_eq!(header(&headers, "connection"), None);
+
+        // The connection is still usable for HTTP requests
+        connection
+            .write_all(b"GET /echo/bob HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        assert_eq!(read_full_response(&mut buf).0, "HTTP/1.1 403 Forbidden");
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn websocket_deflate() {
+        let addr = spawn_server(echo_router(), ServerConfig::default());
+
+        let mut connection = TcpStream::connect(addr).unwrap();
+        let handshake = WEBSOCKET_HANDSHAKE.replace(
+            "\r\n\r\n",
+            "\r\nSec-WebSocket-Extensions: permessage-deflate; server_no_context_takeover\r\n\r\n",
+        );
+        connection.write_all(handshake.as_bytes()).unwrap();
+        let mut buf = BufReader::new(connection.try_clone().unwrap());
+        let (_, headers) = read_response_head(&mut buf);
+        assert_eq!(
+            header(&headers, "sec-websocket-extensions"),
+            Some("permessage-deflate; server_no_context_takeover")
+        );
+        // Messages are compressed once the extension is accepted, "hello bob" included
+        let (head, _) = read_frame(&mut buf);
+        assert_eq!(head, 0xC1);
+
+        // Example of RFC 7692, "Hello" compressed with RSV1 set
+        let hello = [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        connection.write_all(&client_frame(0xC1, &hello)).unwrap();
+        assert_eq!(read_frame(&mut buf), (0xC1, hello.to_vec()));
+        // Fragmented, only the first frame has RSV1 set
+        connection
+            .write_all(&client_frame(0x41, &hello[..3]))
+            .unwrap();
+        connection
+            .write_all(&client_frame(0x80, &hello[3..]))
+            .unwrap();
+        assert_eq!(read_frame(&mut buf), (0xC1, hello.to_vec()));
+        // Control frames can't be compressed
+        connection.write_all(&client_frame(0xC9, b"")).unwrap();
+        assert_eq!(read_frame(&mut buf), (0x88, vec![0x03, 0xEA]));
+    }
+
+    #[test]
+    fn websocket_protocol_errors() {
+        let config = ServerConfig::new().with_max_websocket_message_size(8);
+        let addr = spawn_server(echo_router(), config);
+
+        for (frames, code) in [
+            (vec![vec![0x81, 0x01, b'a']], 1002),
+            (vec![client_frame(0x80, b"a")], 1002),
+            // Compressed without negotiating permessage-deflate
+            (vec![client_frame(0xC1, b"a")], 1002),
+            (vec![client_frame(0x81, &[0xFF])], 1007),
+            (vec![client_frame(0x82, &[0; 9])], 1009),
+            (
+                vec![client_frame(0x02, &[0; 5]), client_frame(0x80, &[0; 5])],
+                1009,
+            ),
+        ] {
+            let mut connection = TcpStream::connect(addr).unwrap();
+            connection
+                .write_all(WEBSOCKET_HANDSHAKE.as_bytes())
+                .unwrap();
+            for frame in &frames {
+                connection.write_all(frame).unwrap();
+            }
+            let mut buf = BufReader::new(connection);
+            read_response_head(&mut buf);
+            read_frame(&mut buf);
+            let (head, payload) = read_frame(&mut buf);
+            assert_eq!(head, 0x88, "{frames:?}");
+            assert_eq!(
+                u16::from_be_bytes([payload[0], payload[1]]),
+                code,
+                "{frames:?}"
+            );
+            assert_eq!(buf.read(&mut [0; 1]).unwrap(), 0);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    mod extractors {
+        use std::{
+            io::{BufReader, Write},
+            net::TcpStream,
+        };
+
+        use crate::{
+            config::ServerConfig,
+            extract::{Json, Path, Query, State},
+            router::Router,
+        };
+
+        use super::{read_response, send, spawn_server};
+
+        #[derive(Clone)]
+        struct AppState {
+            prefix: String,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct Filter {
+            limit: usize,
+            sort: Option<String>,
+        }
+
+        #[derive(serde::Deserialize, serde::Serialize)]
+        struct Comment {
+            text: String,
+        }
+
+        fn create_comment(
+            State(state): State<AppState>,
+            Path((post, id)): Path<(String, u64)>,
+            Query(filter): Query<Filter>,
+            Json(comment): Json<Comment>,
+        ) -> Json<Comment> {
+            Json(Comment {
+                text: format!(
+                    "{}{post}/{id} {} {:?} {}",
+                    state.prefix, filter.limit, filter.sort, comment.text
+                ),
+            })
+        }
+
+        #[test]
+        fn extractors() {
+            let state = AppState {
+                prefix: "post ".to_string(),
+            };
+            let router = Router::with_state(state)
+                .post("/posts/:post/comments/:id", create_comment)
+                .get("/users/:id", |Path(id): Path<u64>| async move {
+                    format!("user {id}")
+                })
+                .post("/echo", |body: String| body);
+            let addr = spawn_server(router, ServerConfig::default());
+
+            let mut connection = TcpStream::connect(addr).unwrap();
+            let body = r#"{"text":"Nice"}"#;
+            let req = format!(
+                "POST /posts/intro/comments/3?limit=10 HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            connection.write_all(req.as_bytes()).unwrap();
+            connection
+                .write_all(b"GET /users/42 HTTP/1.1\r\nHost: localhost\r\n\r\nPOST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello")
+                .unwrap();
+            let mut buf = BufReader::new(connection);
+            assert_eq!(
+                read_response(&mut buf).1,
+                r#"{"text":"post intro/3 10 None Nice"}"#
+            );
+            assert_eq!(read_response(&mut buf).1, "user 42");
+            assert_eq!(read_response(&mut buf).1, "hello");
+
+            // The media type is case-insensitive and can have parameters
+            let req = format!(
+                "POST /posts/intro/comments/3?limit=10 HTTP/1.1\r\nHost: localhost\r\nContent-Type: Application/JSON; charset=utf-8\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            assert_eq!(send(addr, req).2, r#"{"text":"post intro/3 10 None Nice"}"#);
+        }
+
+        #[test]
+        fn extractor_rejections() {
+            let state = AppState {
+                prefix: String::new(),
+            };
+            let router = Router::with_state(state)
+                .post("/posts/:post/comments/:id", create_comment)
+                .get("/users/:id", |Path(id): Path<u64>| format!("user {id}"));
+            let addr = spawn_server(router, ServerConfig::default());
+
+            for (req, status) in [
+                (
+                    "GET /users/abc HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                    "HTTP/1.1 400 Bad Request",
+                ),
+                (
+                    "POST /posts/intro/comments/3 HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}",
+                    "HTTP/1.1 400 Bad Request",
+                ),
+                (
+                    "POST /posts/intro/comments/3?limit=1 HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}",
+                    "HTTP/1.1 400 Bad Request",
+                ),
+                (
+                    "POST /posts/intro/comments/3?limit=1 HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\nContent-Length: 15\r\n\r\n{\"text\":\"Nice\"}",
+                    "HTTP/1.1 415 Unsupported Media Type",
+                ),
+            ] {
+                assert_eq!(send(addr, req).0, status, "{req}");
+            }
+        }
     }
 }
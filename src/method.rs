@@ -1,3 +1,6 @@
+use crate::header::is_token_char;
+
+/// A request method, the ones defined by RFC 9110 and PATCH or any other extension method
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum Method {
     Get,
@@ -6,18 +9,51 @@ pub enum Method {
     Patch,
     Options,
     Delete,
+    Head,
+    Trace,
+    Connect,
+    /// Any other method, e.g. `PROPFIND` or `MKCOL` for WebDAV
+    Extension(String),
 }
 
 impl Method {
-    pub fn parse(method: &str) -> Self {
-        match method.to_lowercase().as_str() {
-            "get" => Self::Get,
-            "post" => Self::Post,
-            "put" => Self::Put,
-            "patch" => Self::Patch,
-            "options" => Self::Options,
-            "delete" => Self::Delete,
-            _ => unimplemented!("Method: {method}"),
-        }
+    /// Parses a method, which is case-sensitive, e.g. `get` is an extension method.
+    ///
+    /// Returns `None` if `method` is not a valid token.
+    pub fn parse(method: &str) -> Option<Self> {
+        let method = match method {
+            "GET" => Self::Get,
+            "POST" => Self::Post,
+            "PUT" => Self::Put,
+            "PATCH" => Self::Patch,
+            "OPTIONS" => Self::Options,
+            "DELETE" => Self::Delete,
+            "HEAD" => Self::Head,
+            "TRACE" => Self::Trace,
+            "CONNECT" => Self::Connect,
+            _ if !method.is_empty() && method.bytes().all(is_token_char) => {
+                Self::Extension(method.to_string())
+            }
+            _ => return None,
+        };
+        Some(method)
+    }
+}
+
+impl std::fmt::Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let method = match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Delete => "DELETE",
+            Method::Head => "HEAD",
+            Method::Trace => "TRACE",
+            Method::Connect => "CONNECT",
+            Method::Extension(method) => method,
+        };
+        write!(f, "{method}")
     }
 }
@@ -0,0 +1,49 @@
+use std::{future::Future, sync::Arc};
+
+use smol::future::Boxed;
+
+use crate::{
+    request::Request,
+    response::{IntoResponse, Response},
+    router::HandlerFn,
+};
+
+pub(crate) type MiddlewareFn<S> = Arc<dyn Fn(Request<S>, Next<S>) -> Boxed<Response> + Send + Sync>;
+
+/// The rest of the middleware stack, down to the handler
+pub struct Next<S: Clone> {
+    handler: HandlerFn<S>,
+}
+
+impl<S: Clone> Next<S> {
+    /// Passes the request to the next middleware, or to the handler for the innermost one
+    pub async fn run(self, req: Request<S>) -> Response {
+        (self.handler)(req).await
+    }
+}
+
+pub(crate) fn into_middleware_fn<S, F, Fut>(middleware: F) -> MiddlewareFn<S>
+where
+    S: Clone,
+    F: Fn(Request<S>, Next<S>) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
+{
+    Arc::new(move |req, next| {
+        let fut = middleware(req, next);
+        Box::pin(async move { fut.await.into_response() })
+    })
+}
+
+/// Returns a handler running `middleware` around `handler`
+pub(crate) fn wrap<S: Clone + 'static>(
+    middleware: MiddlewareFn<S>,
+    handler: HandlerFn<S>,
+) -> HandlerFn<S> {
+    Arc::new(move |req| {
+        let next = Next {
+            handler: handler.clone(),
+        };
+        middleware(req, next)
+    })
+}
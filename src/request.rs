@@ -1,67 +1,142 @@
-use std::{
-    collections::{hash_map::Entry, HashMap},
-    io::{BufRead, BufReader, Read},
-    net::{SocketAddr, TcpStream},
-};
+use std::{collections::HashMap, io::ErrorKind, net::SocketAddr};
+
+use smol::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
 
-use crate::{error::HttpError, HttpResult};
+use crate::{
+    config::ServerConfig,
+    error::HttpError,
+    header::{self, HeaderMap, HeaderName, HeaderValue, TypedHeader},
+    route_path::PathParams,
+    HttpResult,
+};
 
 use super::{http_version::HttpVersion, method::Method};
 
+/// Longest request line, header line or chunk line accepted, terminator included
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+/// Most header fields accepted in a request, or trailer fields after a chunked body
+const MAX_HEADERS: usize = 100;
+
+/// A line read from the request
+enum Line {
+    Complete(String),
+    /// The stream ended before the end of the line
+    Truncated,
+    /// The line is longer than [`MAX_LINE_LENGTH`]
+    TooLong,
+}
+
 #[derive(Debug)]
 pub struct Request<S: Clone> {
     peer_addr: SocketAddr,
     method: Method,
     uri: String,
     query: HashMap<String, String>,
+    query_string: String,
+    path_params: PathParams,
     http_version: HttpVersion,
-    headers: HashMap<String, String>,
+    headers: HeaderMap,
     body: Option<Vec<u8>>,
+    trailers: HeaderMap,
     state: S,
 }
 
 impl<S: Clone> Request<S> {
-    pub fn parse(stream: &mut TcpStream, state: S) -> HttpResult<Self> {
-        let peer_addr = match stream.peer_addr() {
-            Ok(addr) => addr,
-            Err(e) => return Err(HttpError::GetPeerAddrError(e)),
-        };
-
-        let mut buf = BufReader::new(stream);
+    /// Parses a single request from `buf`.
+    ///
+    /// Nothing past the end of the request is consumed, so the next pipelined request of the
+    /// connection can be parsed from the same reader. Bodies bigger than the default maximum body
+    /// size of [`ServerConfig`] are rejected.
+    pub async fn parse<R: AsyncBufRead + Unpin>(
+        buf: &mut R,
+        peer_addr: SocketAddr,
+        state: S,
+    ) -> HttpResult<Self> {
+        let max_body_size = ServerConfig::default().max_body_size;
+        Self::parse_with_max_body_size(buf, peer_addr, state, max_body_size).await
+    }
 
-        let (method, uri, http_version) = Self::get_and_parse_request_line(&mut buf);
+    pub(crate) async fn parse_with_max_body_size<R: AsyncBufRead + Unpin>(
+        buf: &mut R,
+        peer_addr: SocketAddr,
+        state: S,
+        max_body_size: usize,
+    ) -> HttpResult<Self> {
+        let (method, uri, http_version) = Self::get_and_parse_request_line(buf).await?;
 
+        let query_string = match uri.split_once('?') {
+            Some((_, query_string)) => query_string.to_string(),
+            None => String::new(),
+        };
         let (uri, query) = Self::parse_query_from_uri(&uri)?;
 
-        let mut headers = Self::get_and_parse_headers(&mut buf);
+        let headers = Self::get_and_parse_headers(buf).await?;
 
-        let body = if method == Method::Get {
-            None
-        } else {
-            if !headers.contains_key("content-type") {
-                return Err(HttpError::ContentTypeMissing);
-            }
+        // HTTP/1.1 requests must name exactly one host, see RFC 9112 section 3.2
+        if http_version == HttpVersion::HTTP1_1 && headers.get_all(header::HOST).count() != 1 {
+            return Err(HttpError::InvalidHost);
+        }
 
-            match headers.entry("content-length".to_string()) {
-                Entry::Vacant(_) => {
-                    return Err(HttpError::LengthMissing);
+        // Repeated headers are combined, different lengths are as ambiguous as conflicting headers
+        let transfer_encoding = Self::combined(&headers, header::TRANSFER_ENCODING);
+        let content_length = match Self::combined(&headers, header::CONTENT_LENGTH) {
+            Some(lengths) => {
+                let mut lengths = lengths.split(',').map(str::trim);
+                let length = lengths.next().unwrap_or_default().to_string();
+                if lengths.any(|other| other != length) {
+                    return Err(HttpError::ConflictingBodyLength);
                 }
+                Some(length)
+            }
+            None => None,
+        };
 
-                Entry::Occupied(header) => {
-                    let header = header.get();
-                    let content_length_header = match header.parse::<usize>() {
-                        Ok(length) => length,
-                        Err(e) => {
-                            return Err(HttpError::InvalidLength(e));
-                        }
-                    };
-                    let mut body: Vec<u8> = vec![0; content_length_header];
-                    if let Err(e) = buf.read_exact(&mut body) {
-                        return Err(HttpError::InvalidBytesBody(e));
+        let mut trailers = HeaderMap::new();
+        let body = match (transfer_encoding, content_length) {
+            // The body length would be ambiguous, which is how requests get smuggled
+            (Some(_), Some(_)) => return Err(HttpError::ConflictingBodyLength),
+            (Some(transfer_encoding), None) => {
+                // Other transfer codings can't be decoded
+                if !transfer_encoding.trim().eq_ignore_ascii_case("chunked") {
+                    return Err(HttpError::UnsupportedTransferEncoding(transfer_encoding));
+                }
+                let (body, chunk_trailers) = Self::read_chunked_body(buf, max_body_size).await?;
+                trailers = chunk_trailers;
+                Some(body)
+            }
+            (None, Some(content_length)) => {
+                // Only digits are allowed, `usize::parse` would also accept a sign
+                if content_length.is_empty() || !content_length.bytes().all(|b| b.is_ascii_digit())
+                {
+                    return Err(HttpError::InvalidContentLength(content_length));
+                }
+                let content_length = match content_length.parse::<usize>() {
+                    Ok(length) => length,
+                    Err(e) => {
+                        return Err(HttpError::InvalidLength(e));
                     }
-                    Some(body)
+                };
+                if content_length > max_body_size {
+                    return Err(HttpError::BodyTooLarge);
+                }
+
+                let mut body: Vec<u8> = vec![0; content_length];
+                if let Err(e) = buf.read_exact(&mut body).await {
+                    return Err(HttpError::InvalidBytesBody(e));
                 }
+                Some(body)
+            }
+            // Without Content-Length nor Transfer-Encoding the request has no body
+            (None, None) => None,
+        };
+
+        // The body is read even when it is ignored so it isn't mistaken for the next request
+        let body = match body {
+            Some(_) if method == Method::Get => None,
+            Some(body) if !body.is_empty() && !headers.contains_key(header::CONTENT_TYPE) => {
+                return Err(HttpError::ContentTypeMissing);
             }
+            body => body,
         };
 
         let req = Self {
@@ -69,43 +144,166 @@ impl<S: Clone> Request<S> {
             method,
             uri: uri.to_string(),
             query,
+            query_string,
+            path_params: Vec::new(),
             http_version,
             headers,
             body,
+            trailers,
             state,
         };
 
         Ok(req)
     }
 
-    fn get_and_parse_request_line(
-        buf: &mut BufReader<&mut TcpStream>,
-    ) -> (Method, String, HttpVersion) {
-        let mut request_line = String::new();
-        buf.read_line(&mut request_line).unwrap();
-        let mut request_line = request_line.trim().splitn(3, " ");
-        let method = request_line.next().unwrap();
-        let method = Method::parse(method);
-        let uri = request_line.next().unwrap();
-        let http_version = request_line.next().unwrap();
-        let http_version = HttpVersion::parse(http_version).unwrap();
-        (method, uri.to_string(), http_version)
+    /// Reads a line without its terminator, at most [`MAX_LINE_LENGTH`] bytes are read
+    async fn read_line<R: AsyncBufRead + Unpin>(buf: &mut R) -> std::io::Result<Line> {
+        let mut line = String::new();
+        let read = (&mut *buf)
+            .take(MAX_LINE_LENGTH as u64)
+            .read_line(&mut line)
+            .await?;
+        let Some(line) = line.strip_suffix('\n') else {
+            if read == MAX_LINE_LENGTH {
+                return Ok(Line::TooLong);
+            }
+            return Ok(Line::Truncated);
+        };
+        Ok(Line::Complete(
+            line.strip_suffix('\r').unwrap_or(line).to_string(),
+        ))
     }
 
-    fn get_and_parse_headers(buf: &mut BufReader<&mut TcpStream>) -> HashMap<String, String> {
-        let mut headers = HashMap::new();
+    async fn get_and_parse_request_line<R: AsyncBufRead + Unpin>(
+        buf: &mut R,
+    ) -> HttpResult<(Method, String, HttpVersion)> {
+        let request_line = match Self::read_line(buf).await {
+            Ok(Line::Complete(line)) => line,
+            Ok(Line::Truncated) => return Err(HttpError::MalformedRequestLine),
+            Ok(Line::TooLong) => return Err(HttpError::RequestLineTooLong),
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                return Err(HttpError::MalformedRequestLine)
+            }
+            Err(e) => return Err(HttpError::ReadRequestError(e)),
+        };
+
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(uri), Some(http_version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(HttpError::MalformedRequestLine);
+        };
+        if method.is_empty() || uri.is_empty() {
+            return Err(HttpError::MalformedRequestLine);
+        }
+
+        let Some(http_version) = HttpVersion::parse(http_version) else {
+            return Err(HttpError::UnsupportedHttpVersion(http_version.to_string()));
+        };
+        let Some(method) = Method::parse(method) else {
+            return Err(HttpError::MalformedRequestLine);
+        };
+        Ok((method, uri.to_string(), http_version))
+    }
+
+    /// Returns the values of `name` joined with commas, as repeated headers are equivalent to a
+    /// single one with a comma-separated list
+    fn combined(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+        let values: Vec<&str> = headers.get_all(name).map(HeaderValue::as_str).collect();
+        (!values.is_empty()).then(|| values.join(", "))
+    }
+
+    async fn get_and_parse_headers<R: AsyncBufRead + Unpin>(buf: &mut R) -> HttpResult<HeaderMap> {
+        let mut headers = HeaderMap::new();
         loop {
-            let mut line = String::new();
-            buf.read_line(&mut line).unwrap();
-            let line = line.trim();
+            let line = match Self::read_line(buf).await {
+                Ok(Line::Complete(line)) => line,
+                Ok(Line::Truncated) => return Err(HttpError::InvalidHeader),
+                Ok(Line::TooLong) => return Err(HttpError::HeadersTooLarge),
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    return Err(HttpError::InvalidHeader)
+                }
+                Err(e) => return Err(HttpError::ReadRequestError(e)),
+            };
             if line.is_empty() {
                 break;
             }
-            let (header_name, header_value) = line.split_once(": ").unwrap();
+            if headers.len() == MAX_HEADERS {
+                return Err(HttpError::HeadersTooLarge);
+            }
+
+            let Some((header_name, header_value)) = line.split_once(':') else {
+                return Err(HttpError::InvalidHeader);
+            };
+            // No whitespace is allowed between the header name and the colon
+            let Ok(header_name) = HeaderName::try_from(header_name) else {
+                return Err(HttpError::InvalidHeader);
+            };
+            let Ok(header_value) = HeaderValue::try_from(header_value.trim_matches([' ', '\t']))
+            else {
+                return Err(HttpError::InvalidHeader);
+            };
 
-            headers.insert(header_name.to_lowercase(), header_value.to_string());
+            headers.append(header_name, header_value);
         }
-        headers
+        Ok(headers)
+    }
+
+    /// Decodes a body sent with `Transfer-Encoding: chunked` and returns it along with the
+    /// trailer fields following it
+    async fn read_chunked_body<R: AsyncBufRead + Unpin>(
+        buf: &mut R,
+        max_body_size: usize,
+    ) -> HttpResult<(Vec<u8>, HeaderMap)> {
+        let mut body = Vec::new();
+        loop {
+            let chunk_line = match Self::read_line(buf).await {
+                Ok(Line::Complete(line)) => line,
+                Ok(Line::Truncated | Line::TooLong) => return Err(HttpError::InvalidChunk),
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    return Err(HttpError::InvalidChunk)
+                }
+                Err(e) => return Err(HttpError::InvalidBytesBody(e)),
+            };
+
+            // Chunk extensions are allowed after the size but none of them is understood
+            let size = match chunk_line.split_once(';') {
+                Some((size, _extensions)) => size,
+                None => &chunk_line,
+            }
+            .trim_end_matches([' ', '\t']);
+            if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(HttpError::InvalidChunk);
+            }
+            let Ok(size) = usize::from_str_radix(size, 16) else {
+                return Err(HttpError::InvalidChunk);
+            };
+
+            if size == 0 {
+                break;
+            }
+
+            // The size is checked before reading, and the body grows as the chunk arrives
+            let end = body.len().checked_add(size);
+            if end.is_none_or(|end| end > max_body_size) {
+                return Err(HttpError::BodyTooLarge);
+            }
+            match (&mut *buf).take(size as u64).read_to_end(&mut body).await {
+                Ok(read) if read == size => {}
+                Ok(_) => return Err(HttpError::InvalidBytesBody(ErrorKind::UnexpectedEof.into())),
+                Err(e) => return Err(HttpError::InvalidBytesBody(e)),
+            }
+            if !matches!(Self::read_line(buf).await, Ok(Line::Complete(line)) if line.is_empty()) {
+                return Err(HttpError::InvalidChunk);
+            }
+        }
+
+        let trailers = match Self::get_and_parse_headers(buf).await {
+            Ok(trailers) => trailers,
+            Err(HttpError::InvalidHeader) => return Err(HttpError::InvalidChunk),
+            Err(e) => return Err(e),
+        };
+        Ok((body, trailers))
     }
 
     /// Parse the URI and returns the URI and the query
@@ -145,14 +343,67 @@ impl<S: Clone> Request<S> {
         &self.query
     }
 
+    /// The query as sent, without the leading `?`
+    pub fn query_string(&self) -> &str {
+        &self.query_string
+    }
+
+    /// Parameters captured from the dynamic segments of the matched route, percent-decoded, in
+    /// route order
+    pub fn path_params(&self) -> &[(String, String)] {
+        &self.path_params
+    }
+
+    /// Returns the percent-decoded value of the path parameter `name`, e.g. `id` for `/users/:id`
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.path_params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn set_path_params(&mut self, path_params: PathParams) {
+        self.path_params = path_params;
+    }
+
     pub fn http_version(&self) -> &HttpVersion {
         &self.http_version
     }
 
-    pub fn headers(&self) -> &HashMap<String, String> {
+    pub fn headers(&self) -> &HeaderMap {
         &self.headers
     }
 
+    /// Parses a header, e.g. `req.typed_header::<Accept>()`. Returns `None` if it is missing or
+    /// invalid.
+    pub fn typed_header<H: TypedHeader>(&self) -> Option<H> {
+        self.headers.typed_get()
+    }
+
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    /// Trailer fields sent after a chunked body
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    /// Whether the client wants the connection to stay open after the response, by default with
+    /// HTTP/1.1 and only when asked with `Connection: keep-alive` with HTTP/1.0
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |name: &str| {
+            self.headers
+                .get_all(header::CONNECTION)
+                .flat_map(|connection| connection.as_str().split(','))
+                .any(|option| option.trim().eq_ignore_ascii_case(name))
+        };
+        match self.http_version {
+            HttpVersion::HTTP1_0 => has_option("keep-alive"),
+            HttpVersion::HTTP1_1 => !has_option("close"),
+        }
+    }
+
     ///Consumes the body
     pub fn bytes_body(&mut self) -> Option<Vec<u8>> {
         self.body.take()
@@ -178,3 +429,298 @@ impl<S: Clone> Request<S> {
         &self.state
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::{
+        error::HttpError,
+        header::{Accept, Authorization, HeaderValue, Range},
+        http_version::HttpVersion,
+        method::Method,
+        HttpResult,
+    };
+
+    use super::Request;
+
+    fn parse(raw: &str) -> HttpResult<Request<()>> {
+        let peer_addr: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        smol::block_on(Request::parse(&mut raw.as_bytes(), peer_addr, ()))
+    }
+
+    #[test]
+    fn valid_request() {
+        let req =
+            parse("GET /users?page=2 HTTP/1.1\r\nHost:example.com\r\nAccept:  text/html \r\n\r\n")
+                .unwrap();
+        assert_eq!(req.method(), &Method::Get);
+        assert_eq!(req.uri(), "/users");
+        assert_eq!(req.query().get("page").map(String::as_str), Some("2"));
+        assert_eq!(
+            req.headers().get("host").map(HeaderValue::as_str),
+            Some("example.com")
+        );
+        assert_eq!(
+            req.headers().get("accept").map(HeaderValue::as_str),
+            Some("text/html")
+        );
+    }
+
+    #[test]
+    fn malformed_request_line() {
+        for raw in [
+            "\r\n\r\n",
+            "GET\r\n\r\n",
+            "GET /\r\n\r\n",
+            "GET  / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET / HTTP/1.1 extra\r\n\r\n",
+            "GET / HTTP/1.1",
+            "GE(T / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ] {
+            assert!(
+                matches!(parse(raw), Err(HttpError::MalformedRequestLine)),
+                "{raw:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn size_limits() {
+        let long_uri = format!(
+            "GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "a".repeat(super::MAX_LINE_LENGTH)
+        );
+        assert!(matches!(
+            parse(&long_uri),
+            Err(HttpError::RequestLineTooLong)
+        ));
+        let long_header = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nx-long: {}\r\n\r\n",
+            "a".repeat(super::MAX_LINE_LENGTH)
+        );
+        assert!(matches!(
+            parse(&long_header),
+            Err(HttpError::HeadersTooLarge)
+        ));
+        let headers = |count| {
+            // Host counts as one of them
+            let headers: String = (1..count).map(|i| format!("x-{i}: a\r\n")).collect();
+            format!("GET / HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n")
+        };
+        assert!(parse(&headers(super::MAX_HEADERS)).is_ok());
+        assert!(matches!(
+            parse(&headers(super::MAX_HEADERS + 1)),
+            Err(HttpError::HeadersTooLarge)
+        ));
+    }
+
+    #[test]
+    fn extension_methods() {
+        for (raw, expected) in [
+            ("PROPFIND / HTTP/1.1\r\nHost: localhost\r\n\r\n", "PROPFIND"),
+            ("BREW / HTTP/1.1\r\nHost: localhost\r\n\r\n", "BREW"),
+            // Methods are case-sensitive
+            ("get / HTTP/1.1\r\nHost: localhost\r\n\r\n", "get"),
+        ] {
+            let req = parse(raw).unwrap();
+            assert_eq!(req.method(), &Method::Extension(expected.to_string()));
+        }
+        assert_eq!(
+            parse("DELETE / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap()
+                .method(),
+            &Method::Delete
+        );
+    }
+
+    #[test]
+    fn http_1_0() {
+        let req = parse("GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(req.http_version(), &HttpVersion::HTTP1_0);
+        assert!(!req.keep_alive());
+
+        let req = parse("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").unwrap();
+        assert!(req.keep_alive());
+
+        let req = parse("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert_eq!(req.http_version(), &HttpVersion::HTTP1_1);
+        assert!(req.keep_alive());
+    }
+
+    #[test]
+    fn host() {
+        for raw in [
+            "GET / HTTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a.com\r\nHost: b.com\r\n\r\n",
+        ] {
+            assert!(matches!(parse(raw), Err(HttpError::InvalidHost)), "{raw}");
+        }
+        // Host is optional in HTTP/1.0
+        assert!(parse("GET / HTTP/1.0\r\n\r\n").is_ok());
+    }
+
+    #[test]
+    fn unsupported_version() {
+        assert!(matches!(
+            parse("GET / HTTP/2.0\r\n\r\n"),
+            Err(HttpError::UnsupportedHttpVersion(version)) if version == "HTTP/2.0"
+        ));
+    }
+
+    #[test]
+    fn invalid_headers() {
+        for raw in [
+            "GET / HTTP/1.1\r\nHost\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost : example.com\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: localhost\r\n: example.com\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: example.com\r\n",
+            "GET / HTTP/1.1\r\nHost: localhost\r\nX(Header): value\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: localhost\r\nX-Header: a\rb\r\n\r\n",
+        ] {
+            assert!(
+                matches!(parse(raw), Err(HttpError::InvalidHeader)),
+                "{raw:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn repeated_headers() {
+        let req = parse(
+            "GET / HTTP/1.1\r\nAccept: text/html\r\nHost: example.com\r\nACCEPT: */*\r\n\r\n",
+        )
+        .unwrap();
+        let accept: Vec<&str> = req
+            .headers()
+            .get_all("Accept")
+            .map(HeaderValue::as_str)
+            .collect();
+        assert_eq!(accept, ["text/html", "*/*"]);
+        let names: Vec<&str> = req
+            .headers()
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["accept", "host", "accept"]);
+    }
+
+    #[test]
+    fn typed_headers() {
+        let req = parse(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nAccept: text/html\r\nAccept: */*;q=0.1\r\nRange: bytes=oops\r\n\r\n",
+        )
+        .unwrap();
+        let accept = req.typed_header::<Accept>().unwrap();
+        assert_eq!(accept.quality("text/html"), 1000);
+        assert_eq!(accept.quality("image/png"), 100);
+        assert!(req.typed_header::<Range>().is_none());
+        assert!(req.typed_header::<Authorization>().is_none());
+    }
+
+    #[test]
+    fn chunked_body() {
+        let mut req = parse(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nHello\r\n\
+             7;name=value\r\n, world\r\n\
+             0\r\n\
+             Expires: never\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            req.trailers().get("expires").map(HeaderValue::as_str),
+            Some("never")
+        );
+        assert_eq!(req.string_body().as_deref(), Some("Hello, world"));
+    }
+
+    #[test]
+    fn invalid_chunked_bodies() {
+        let prefix =
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\r\n";
+        for chunks in [
+            "5\r\nHello\r\n",
+            "x\r\nHello\r\n0\r\n\r\n",
+            "5\r\nHello, world\r\n0\r\n\r\n",
+            "+5\r\nHello\r\n0\r\n\r\n",
+            "fffffffffffffffffffff\r\nHello\r\n0\r\n\r\n",
+        ] {
+            assert!(
+                matches!(
+                    parse(&format!("{prefix}{chunks}")),
+                    Err(HttpError::InvalidChunk)
+                ),
+                "{chunks:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn body_too_large() {
+        let peer_addr: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let parse = |raw: &str| {
+            smol::block_on(Request::parse_with_max_body_size(
+                &mut raw.as_bytes(),
+                peer_addr,
+                (),
+                4,
+            ))
+        };
+        let chunked = "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\n\
+                       Transfer-Encoding: chunked\r\n\r\n";
+        assert_eq!(
+            parse(&format!("{chunked}2\r\nab\r\n2\r\ncd\r\n0\r\n\r\n"))
+                .unwrap()
+                .body
+                .unwrap(),
+            b"abcd"
+        );
+        for raw in [
+            format!("{chunked}2\r\nab\r\n3\r\ncde\r\n0\r\n\r\n"),
+            // Sizes which can't be allocated or overflow are rejected before reading anything
+            format!("{chunked}ffffffffff\r\n"),
+            format!("{chunked}1\r\na\r\nffffffffffffffff\r\n"),
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nabcde"
+                .to_string(),
+        ] {
+            assert!(
+                matches!(parse(&raw), Err(HttpError::BodyTooLarge)),
+                "{raw:?}"
+            );
+        }
+        // A chunk shorter than its size
+        assert!(matches!(
+            parse(&format!("{chunked}4\r\nab")),
+            Err(HttpError::InvalidBytesBody(_))
+        ));
+    }
+
+    #[test]
+    fn conflicting_body_length() {
+        assert!(matches!(
+            parse(
+                "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 5\r\nHello\r\n0\r\n\r\n"
+            ),
+            Err(HttpError::ConflictingBodyLength)
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nHello!"),
+            Err(HttpError::ConflictingBodyLength)
+        ));
+        let mut req = parse(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\nContent-Length: 5, 5\r\n\r\nHello",
+        )
+        .unwrap();
+        assert_eq!(req.string_body().as_deref(), Some("Hello"));
+    }
+
+    #[test]
+    fn unsupported_transfer_encoding() {
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"),
+            Err(HttpError::UnsupportedTransferEncoding(_))
+        ));
+    }
+}
@@ -1,6 +1,15 @@
-use std::{collections::HashMap, convert::Infallible, io::Write, net::TcpStream};
+use std::{convert::Infallible, fmt::Debug};
 
-use crate::header;
+use smol::{
+    io::{AsyncWrite, AsyncWriteExt},
+    stream::{Stream, StreamExt},
+};
+
+use crate::{
+    body::{Body, Bytes},
+    header::{self, ContentType, HeaderMap, HeaderName, HeaderValue, TypedHeader},
+    ws::OnUpgrade,
+};
 
 use super::{http_version::HttpVersion, status_code::StatusCode};
 
@@ -8,38 +17,108 @@ use super::{http_version::HttpVersion, status_code::StatusCode};
 pub struct Response {
     pub http_version: HttpVersion,
     pub status_code: StatusCode,
-    pub headers: HashMap<String, String>,
-    pub body: Option<String>,
+    pub headers: HeaderMap,
+    pub body: Body,
+    /// Set when the connection switches to the WebSocket protocol after this response
+    pub(crate) upgrade: Option<OnUpgrade>,
 }
 
 impl Response {
-    pub fn send_to_stream(&mut self, stream: &mut TcpStream) {
-        let content_length = if let Some(ref body) = self.body {
-            body.len()
-        } else {
-            0
-        };
-
-        self.headers.insert(
-            header::CONTENT_LENGTH.to_string(),
-            content_length.to_string(),
-        );
+    pub async fn send_to_stream<W: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut W,
+    ) -> std::io::Result<()> {
+        self.write_to_stream(stream, true).await
+    }
+
+    /// Sends the response to a HEAD request, with the headers it would have for a GET request,
+    /// `Content-Length` included, but without the body
+    pub async fn send_head_to_stream<W: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut W,
+    ) -> std::io::Result<()> {
+        self.write_to_stream(stream, false).await
+    }
+
+    async fn write_to_stream<W: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut W,
+        with_body: bool,
+    ) -> std::io::Result<()> {
+        // 1xx and 204 responses can't have a body, nor a length
+        let bodyless =
+            self.status_code.is_informational() || self.status_code == StatusCode::NoContent;
+        if bodyless {
+            self.body = Body::Empty;
+            self.headers.remove(header::CONTENT_LENGTH);
+            self.headers.remove(header::TRANSFER_ENCODING);
+        }
 
-        println!("Response : {self:#?}");
+        match self.body {
+            Body::Empty if bodyless => {}
+            Body::Empty => {
+                self.headers
+                    .insert(header::CONTENT_LENGTH, HeaderValue::from(0));
+            }
+            Body::Full(ref body) => {
+                self.headers
+                    .insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+            }
+            Body::Stream(_) => {
+                self.headers.remove(header::CONTENT_LENGTH);
+                // HTTP/1.0 clients don't know chunked encoding, the end of the body is marked by
+                // closing the connection instead
+                if self.http_version == HttpVersion::HTTP1_1 {
+                    self.headers.insert(
+                        header::TRANSFER_ENCODING,
+                        HeaderValue::from_static("chunked"),
+                    );
+                }
+            }
+        }
 
         let mut final_res = format!("{} {}\r\n", self.http_version, self.status_code);
 
-        for (name, value) in &self.headers {
+        for (name, value) in self.headers.iter() {
             final_res += &format!("{}: {}\r\n", name, value);
         }
 
         final_res += "\r\n";
 
-        if let Some(ref body) = self.body {
-            final_res += body;
+        let mut final_res = final_res.into_bytes();
+        if !with_body {
+            stream.write_all(&final_res).await?;
+            return stream.flush().await;
+        }
+        if let Body::Full(ref body) = self.body {
+            final_res.extend_from_slice(body);
+        }
+
+        stream.write_all(&final_res).await?;
+
+        if let Body::Stream(ref mut chunks) = self.body {
+            if self.http_version == HttpVersion::HTTP1_0 {
+                while let Some(chunk) = chunks.next().await {
+                    stream.write_all(&chunk).await?;
+                    stream.flush().await?;
+                }
+                return Ok(());
+            }
+            while let Some(chunk) = chunks.next().await {
+                // An empty chunk would mark the end of the body
+                if chunk.is_empty() {
+                    continue;
+                }
+                let mut encoded = format!("{:x}\r\n", chunk.len()).into_bytes();
+                encoded.extend_from_slice(&chunk);
+                encoded.extend_from_slice(b"\r\n");
+                stream.write_all(&encoded).await?;
+                stream.flush().await?;
+            }
+            stream.write_all(b"0\r\n\r\n").await?;
         }
 
-        stream.write(final_res.as_bytes()).unwrap();
+        stream.flush().await
     }
 }
 
@@ -53,9 +132,9 @@ impl IntoResponse for Response {
     }
 }
 
-impl<E: IntoResponse> IntoResponse for Result<E, Infallible> {
+impl IntoResponse for Infallible {
     fn into_response(self) -> Response {
-        self.unwrap().into_response()
+        match self {}
     }
 }
 
@@ -76,17 +155,76 @@ impl IntoResponse for &str {
     }
 }
 
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        ResponseBuilder::new()
+            .with_bytes(self, BodyKind::Text)
+            .build()
+    }
+}
+
+impl IntoResponse for Bytes {
+    fn into_response(self) -> Response {
+        ResponseBuilder::new()
+            .with_bytes(self, BodyKind::Binary)
+            .build()
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        ResponseBuilder::new()
+            .with_bytes(self, BodyKind::Binary)
+            .build()
+    }
+}
+
+impl IntoResponse for &'static [u8] {
+    fn into_response(self) -> Response {
+        ResponseBuilder::new()
+            .with_bytes(self, BodyKind::Binary)
+            .build()
+    }
+}
+
+impl IntoResponse for (BodyKind, Vec<u8>) {
+    fn into_response(self) -> Response {
+        let (kind, body) = self;
+        ResponseBuilder::new().with_bytes(body, kind).build()
+    }
+}
+
+impl IntoResponse for (ContentType, Vec<u8>) {
+    fn into_response(self) -> Response {
+        let (ContentType(content_type), body) = self;
+        ResponseBuilder::new()
+            .with_bytes(body, BodyKind::Other(content_type))
+            .build()
+    }
+}
+
+impl IntoResponse for (BodyKind, &'static [u8]) {
+    fn into_response(self) -> Response {
+        let (kind, body) = self;
+        ResponseBuilder::new().with_bytes(body, kind).build()
+    }
+}
+
 pub struct ResponseBuilder {
     http_version: Option<HttpVersion>,
     status_code: Option<StatusCode>,
-    headers: HashMap<String, String>,
-    body: Option<String>,
+    headers: HeaderMap,
+    body: Body,
 }
 
 pub enum BodyKind {
     Json,
     Html,
     Text,
+    /// Arbitrary bytes, `application/octet-stream`
+    Binary,
+    /// Any other media type, e.g. `image/png`
+    Other(String),
 }
 
 impl BodyKind {
@@ -95,17 +233,25 @@ impl BodyKind {
             BodyKind::Html => "text/html",
             BodyKind::Json => "application/json",
             BodyKind::Text => "text/plain",
+            BodyKind::Binary => "application/octet-stream",
+            BodyKind::Other(content_type) => content_type,
         }
     }
 }
 
+impl Default for ResponseBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ResponseBuilder {
     pub fn new() -> Self {
         Self {
             http_version: None,
             status_code: None,
-            headers: HashMap::new(),
-            body: None,
+            headers: HeaderMap::new(),
+            body: Body::Empty,
         }
     }
     pub fn with_http_version(mut self, http_version: HttpVersion) -> Self {
@@ -116,24 +262,207 @@ impl ResponseBuilder {
         self.status_code = Some(status_code);
         self
     }
-    pub fn append_header(mut self, name: &str, value: &str) -> Self {
-        self.headers.insert(name.to_string(), value.to_string());
+    /// Adds a header, keeping the values already added for `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` or `value` is invalid, e.g. if `value` contains CR or LF.
+    pub fn append_header<N, V>(mut self, name: N, value: V) -> Self
+    where
+        N: TryInto<HeaderName>,
+        N::Error: Debug,
+        V: TryInto<HeaderValue>,
+        V::Error: Debug,
+    {
+        let name = name.try_into().expect("Invalid header name");
+        let value = value.try_into().expect("Invalid header value");
+        self.headers.append(name, value);
         self
     }
-    pub fn with_body(mut self, body: &str, kind: BodyKind) -> Self {
-        self.body = Some(body.to_string());
-        self.headers.insert(
-            header::CONTENT_TYPE.to_string(),
-            kind.content_type().to_string(),
-        );
+    /// Sets a typed header, replacing its other values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the formatted header is not a valid header value.
+    pub fn typed_header<H: TypedHeader>(mut self, header: H) -> Self {
+        let value = HeaderValue::try_from(header.to_string()).expect("Invalid header value");
+        self.headers.insert(H::NAME, value);
+        self
+    }
+    /// Replaces the `Content-Type` header.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the content type of [`BodyKind::Other`] is not a valid header value.
+    fn with_content_type(mut self, kind: BodyKind) -> Self {
+        let content_type =
+            HeaderValue::try_from(kind.content_type()).expect("Invalid content type");
+        self.headers.insert(header::CONTENT_TYPE, content_type);
         self
     }
+    pub fn with_body(mut self, body: &str, kind: BodyKind) -> Self {
+        self.body = body.into();
+        self.with_content_type(kind)
+    }
+    /// Sets a body that doesn't have to be UTF-8, such as an image or an archive
+    pub fn with_bytes(mut self, body: impl Into<Bytes>, kind: BodyKind) -> Self {
+        self.body = Body::Full(body.into());
+        self.with_content_type(kind)
+    }
+    /// Sends the body chunk by chunk as `stream` yields them, without buffering it
+    pub fn with_stream<St, T>(mut self, stream: St, kind: BodyKind) -> Self
+    where
+        St: Stream<Item = T> + Send + 'static,
+        T: Into<Vec<u8>> + 'static,
+    {
+        self.body = Body::from_stream(stream);
+        self.with_content_type(kind)
+    }
+    /// Sends the body chunk by chunk as `chunks` yields them, without buffering it
+    pub fn with_chunks<I>(mut self, chunks: I, kind: BodyKind) -> Self
+    where
+        I: IntoIterator,
+        I::IntoIter: Send + 'static,
+        I::Item: Into<Vec<u8>> + 'static,
+    {
+        self.body = Body::from_chunks(chunks);
+        self.with_content_type(kind)
+    }
     pub fn build(self) -> Response {
         Response {
             http_version: self.http_version.unwrap_or(HttpVersion::HTTP1_1),
             status_code: self.status_code.unwrap_or(StatusCode::Ok),
             headers: self.headers,
             body: self.body,
+            upgrade: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use smol::stream::StreamExt;
+
+    use crate::{
+        body::{Body, Bytes},
+        header::{CacheControl, CacheDirective, ContentType, ETag, HeaderValue},
+        http_version::HttpVersion,
+    };
+
+    use super::{BodyKind, IntoResponse, ResponseBuilder};
+
+    fn send(builder: ResponseBuilder) -> String {
+        let mut res = builder.build();
+        let mut sent = Vec::new();
+        smol::block_on(res.send_to_stream(&mut sent)).unwrap();
+        String::from_utf8(sent).unwrap()
+    }
+
+    #[test]
+    fn full_body() {
+        let sent = send(ResponseBuilder::new().with_body("Hello", BodyKind::Text));
+        assert!(sent.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(sent.contains("content-length: 5\r\n"));
+        assert!(!sent.contains("transfer-encoding"));
+        assert!(sent.ends_with("\r\n\r\nHello"));
+    }
+
+    #[test]
+    fn repeated_headers() {
+        let sent = send(
+            ResponseBuilder::new()
+                .append_header("Set-Cookie", "a=1")
+                .append_header("set-cookie", "b=2"),
+        );
+        assert!(sent.contains("set-cookie: a=1\r\nset-cookie: b=2\r\n"));
+    }
+
+    #[test]
+    fn typed_headers() {
+        let sent = send(
+            ResponseBuilder::new()
+                .typed_header(ETag {
+                    weak: true,
+                    tag: "v1".to_string(),
+                })
+                .typed_header(CacheControl(vec![CacheDirective::MaxAge(60)])),
+        );
+        assert!(sent.contains("etag: W/\"v1\"\r\n"));
+        assert!(sent.contains("cache-control: max-age=60\r\n"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid header value")]
+    fn header_injection() {
+        let _ = ResponseBuilder::new().append_header("location", "/\r\nSet-Cookie: admin=1");
+    }
+
+    #[test]
+    fn binary_body() {
+        let png: &'static [u8] = &[0x89, b'P', b'N', b'G', 0xff, 0x00];
+        let mut res = (BodyKind::Other("image/png".to_string()), png).into_response();
+        assert_eq!(
+            res.headers.get("content-type").map(HeaderValue::as_str),
+            Some("image/png")
+        );
+        let mut sent = Vec::new();
+        smol::block_on(res.send_to_stream(&mut sent)).unwrap();
+        assert!(sent.ends_with(b"\r\n\r\n\x89PNG\xff\x00"));
+
+        let res = (ContentType("image/gif".to_string()), b"GIF89a".to_vec()).into_response();
+        assert_eq!(
+            res.headers.get("content-type").map(HeaderValue::as_str),
+            Some("image/gif")
+        );
+        assert!(matches!(res.body, Body::Full(ref body) if **body == *b"GIF89a"));
+
+        let res = vec![0xde, 0xad, 0xbe, 0xef].into_response();
+        assert_eq!(
+            res.headers.get("content-type").map(HeaderValue::as_str),
+            Some("application/octet-stream")
+        );
+        assert!(matches!(res.body, Body::Full(ref body) if **body == [0xde, 0xad, 0xbe, 0xef]));
+    }
+
+    #[test]
+    fn shared_body() {
+        let shared: Arc<[u8]> = Arc::from(&b"cached"[..]);
+        let first = Bytes::from(shared.clone()).into_response();
+        let second = Bytes::from(shared.clone()).into_response();
+        assert!(matches!(first.body, Body::Full(Bytes::Shared(_))));
+        assert!(matches!(second.body, Body::Full(ref body) if **body == *b"cached"));
+        assert_eq!(Arc::strong_count(&shared), 3);
+    }
+
+    #[test]
+    fn chunked_body() {
+        let chunks = vec!["id,name\n", "", "1,a very long name\n"];
+        let sent = send(ResponseBuilder::new().with_chunks(chunks, BodyKind::Text));
+        assert!(sent.contains("transfer-encoding: chunked\r\n"));
+        assert!(!sent.contains("content-length"));
+        assert!(sent.ends_with("\r\n\r\n8\r\nid,name\n\r\n13\r\n1,a very long name\n\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn streamed_body() {
+        let stream = smol::stream::iter(1..=3).map(|i| i.to_string());
+        let sent = send(ResponseBuilder::new().with_stream(stream, BodyKind::Text));
+        assert!(sent.ends_with("\r\n\r\n1\r\n1\r\n1\r\n2\r\n1\r\n3\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn http_1_0_streamed_body() {
+        let stream = smol::stream::iter(1..=3).map(|i| i.to_string());
+        let sent = send(
+            ResponseBuilder::new()
+                .with_http_version(HttpVersion::HTTP1_0)
+                .with_stream(stream, BodyKind::Text),
+        );
+        assert!(sent.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(!sent.contains("transfer-encoding"));
+        assert!(!sent.contains("content-length"));
+        assert!(sent.ends_with("\r\n\r\n123"));
+    }
+}
@@ -1,9 +1,103 @@
-use crate::router::HandlerFn;
+use std::collections::HashMap;
+
+use crate::{error::RouteError, method::Method, router::HandlerFn};
+
+/// Path parameters captured by a route, as `(name, value)` in route order
+pub type PathParams = Vec<(String, String)>;
+
+/// Handlers of a single route, by method
+pub(crate) type Handlers<S> = HashMap<Method, HandlerFn<S>>;
 
 pub struct Node<S: Clone> {
+    /// Static children, kept sorted by key so lookups can binary search
     nodes: Vec<Node<S>>,
+    /// Dynamic child matching any single segment, its key is the parameter name
+    param: Option<Box<Node<S>>>,
+    /// Child matching all the remaining segments, its key is the parameter name
+    catch_all: Option<Box<Node<S>>>,
     key: String,
-    pub(crate) handler: Option<HandlerFn<S>>,
+    /// Handlers registered for this exact path, by method
+    pub(crate) handlers: Handlers<S>,
+}
+
+/// A single segment of a route path as written when registering the route
+enum Segment<'a> {
+    Static(&'a str),
+    /// `:name` or `{name}`, optional when suffixed with `?`
+    Param {
+        name: &'a str,
+        optional: bool,
+    },
+    /// `*name` or `{*name}`
+    CatchAll(&'a str),
+}
+
+impl<'a> Segment<'a> {
+    fn parse(segment: &'a str) -> Self {
+        let name = if let Some(name) = segment.strip_prefix(':') {
+            name
+        } else if let Some(name) = segment
+            .strip_prefix('{')
+            .and_then(|segment| segment.strip_suffix('}'))
+        {
+            if let Some(name) = name.strip_prefix('*') {
+                return Self::CatchAll(name);
+            }
+            name
+        } else if let Some(name) = segment.strip_prefix('*') {
+            return Self::CatchAll(name);
+        } else {
+            return Self::Static(segment);
+        };
+
+        match name.strip_suffix('?') {
+            Some(name) => Self::Param {
+                name,
+                optional: true,
+            },
+            None => Self::Param {
+                name,
+                optional: false,
+            },
+        }
+    }
+}
+
+pub(crate) fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// Percent-decodes the values of `params`, returns the name of the first one which isn't UTF-8
+/// once decoded
+pub(crate) fn decode_params(params: PathParams) -> Result<PathParams, String> {
+    params
+        .into_iter()
+        .map(|(name, value)| match percent_decode(&value) {
+            Some(value) => Ok((name, value)),
+            None => Err(name),
+        })
+        .collect()
+}
+
+/// Decodes the `%XX` sequences of `value`, a `%` not followed by two hex digits is kept as is
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let hex = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i..] {
+            [b'%', high, low, ..] if hex(high).is_some() && hex(low).is_some() => {
+                decoded.push(hex(high)? << 4 | hex(low)?);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
 }
 
 impl<S: Clone> Node<S> {
@@ -11,65 +105,569 @@ impl<S: Clone> Node<S> {
         Self {
             key: key.to_string(),
             nodes: Vec::new(),
-            handler: None,
+            param: None,
+            catch_all: None,
+            handlers: HashMap::new(),
         }
     }
 
-    pub fn insert(&mut self, path: &str, handler: HandlerFn<S>) {
-        if let Some((root, path)) = path.split_once('/') {
-            if root.is_empty() {
-                self.insert(path, handler);
-                return;
+    /// Registers `handler` for `method` requests to `path`.
+    ///
+    /// Nothing is registered if an error is returned.
+    pub fn insert(
+        &mut self,
+        path: &str,
+        method: Method,
+        handler: HandlerFn<S>,
+    ) -> Result<(), RouteError> {
+        let segments: Vec<Segment> = segments(path).map(Segment::parse).collect();
+        let mut optional = false;
+        for (i, segment) in segments.iter().enumerate() {
+            match *segment {
+                Segment::CatchAll(_) if i != segments.len() - 1 => {
+                    return Err(RouteError::CatchAllNotLast(path.to_string()));
+                }
+                Segment::Param { optional: true, .. } => optional = true,
+                _ if optional => return Err(RouteError::OptionalNotLast(path.to_string())),
+                _ => {}
             }
+        }
+        self.insert_segments(&segments, path, method, handler)
+    }
 
-            let res = self.nodes.binary_search_by(|n| root.cmp(&n.key));
-            match res {
-                Ok(i) => {
-                    let Some(node) = self.nodes.get_mut(i) else {
-                        panic!("jsp");
-                    };
-                    node.insert(path, handler);
-                }
-                Err(n) => {
-                    let mut node = Node::new(root);
-                    node.insert(path, handler);
-                    self.nodes.insert(n, node);
-                }
+    fn insert_segments(
+        &mut self,
+        segments: &[Segment],
+        path: &str,
+        method: Method,
+        handler: HandlerFn<S>,
+    ) -> Result<(), RouteError> {
+        if self.handlers.contains_key(&method) {
+            // The route also ends here when all the remaining segments are optional
+            if segments.is_empty() || matches!(segments[0], Segment::Param { optional: true, .. }) {
+                return Err(RouteError::Duplicate(method, path.to_string()));
             }
-        } else {
-            if path.is_empty() {
-                self.handler = Some(handler);
-            } else {
-                let mut node = Node::new(path);
-                node.handler = Some(handler);
-                self.nodes.push(node);
+        }
+
+        let Some((segment, rest)) = segments.split_first() else {
+            self.handlers.insert(method, handler);
+            return Ok(());
+        };
+
+        // Conflicts are all checked before the handler is registered anywhere
+        self.child_mut(segment, path)?.insert_segments(
+            rest,
+            path,
+            method.clone(),
+            handler.clone(),
+        )?;
+        if let Segment::Param { optional: true, .. } = segment {
+            self.handlers.insert(method, handler);
+        }
+        Ok(())
+    }
+
+    /// Returns the child matching `segment`, creating it if needed.
+    ///
+    /// Dynamic segments at the same level must have the same name, otherwise the routes would be
+    /// ambiguous.
+    fn child_mut(&mut self, segment: &Segment, path: &str) -> Result<&mut Node<S>, RouteError> {
+        let (child, name) = match *segment {
+            Segment::Static(key) => {
+                let i = match self.nodes.binary_search_by(|n| n.key.as_str().cmp(key)) {
+                    Ok(i) => i,
+                    Err(i) => {
+                        self.nodes.insert(i, Node::new(key));
+                        i
+                    }
+                };
+                return Ok(&mut self.nodes[i]);
             }
+            Segment::Param { name, .. } => (&mut self.param, name),
+            Segment::CatchAll(name) => (&mut self.catch_all, name),
+        };
+        let child = child.get_or_insert_with(|| Box::new(Node::new(name)));
+        if child.key != name {
+            return Err(RouteError::Ambiguous {
+                path: path.to_string(),
+                name: name.to_string(),
+                existing: child.key.clone(),
+            });
+        }
+        Ok(child)
+    }
+
+    /// Returns the routes of the trie as `(path, method, handler)`, with parameters written as
+    /// `:name` and catch-all segments as `*name`
+    pub(crate) fn into_routes(self) -> Vec<(String, Method, HandlerFn<S>)> {
+        let mut routes = Vec::new();
+        self.collect_routes("", &mut routes);
+        routes
+    }
+
+    fn collect_routes(&self, path: &str, routes: &mut Vec<(String, Method, HandlerFn<S>)>) {
+        for (method, handler) in &self.handlers {
+            let path = if path.is_empty() { "/" } else { path };
+            routes.push((path.to_string(), method.clone(), handler.clone()));
+        }
+        for node in &self.nodes {
+            node.collect_routes(&format!("{path}/{}", node.key), routes);
+        }
+        if let Some(ref node) = self.param {
+            node.collect_routes(&format!("{path}/:{}", node.key), routes);
         }
+        if let Some(ref node) = self.catch_all {
+            node.collect_routes(&format!("{path}/*{}", node.key), routes);
+        }
+    }
+
+    /// Replaces every handler of the trie with `f(handler)`
+    pub(crate) fn map_handlers(&mut self, f: &impl Fn(HandlerFn<S>) -> HandlerFn<S>) {
+        for handler in self.handlers.values_mut() {
+            *handler = f(handler.clone());
+        }
+        for node in &mut self.nodes {
+            node.map_handlers(f);
+        }
+        if let Some(ref mut node) = self.param {
+            node.map_handlers(f);
+        }
+        if let Some(ref mut node) = self.catch_all {
+            node.map_handlers(f);
+        }
+    }
+
+    /// Returns the handlers of the route matching `path`, by method, along with the captured path
+    /// parameters in the order they appear in the route.
+    ///
+    /// Static segments take priority over dynamic ones, which take priority over catch-all ones.
+    pub fn get(&self, path: &str) -> Option<(&Handlers<S>, PathParams)> {
+        self.find(path, |_| true)
+    }
+
+    /// Like [`Node::get`], but skips the routes whose handlers are rejected by `accept`, e.g. the
+    /// ones without a handler for the request method, so that a dynamic route can match instead.
+    pub(crate) fn find(
+        &self,
+        path: &str,
+        accept: impl Fn(&Handlers<S>) -> bool,
+    ) -> Option<(&Handlers<S>, PathParams)> {
+        let segments: Vec<&str> = segments(path).collect();
+        let mut params = Vec::new();
+        let handlers = self.lookup(&segments, &mut params, &accept)?;
+        // Parameters are pushed while unwinding the lookup
+        params.reverse();
+        Some((handlers, params))
+    }
+
+    /// Returns the handlers of every route matching `path`
+    pub(crate) fn get_all(&self, path: &str) -> Vec<&Handlers<S>> {
+        let segments: Vec<&str> = segments(path).collect();
+        let mut matches = Vec::new();
+        self.collect_matches(&segments, &mut matches);
+        matches
     }
 
-    pub fn get(&self, path: &str) -> Option<HandlerFn<S>> {
-        if let Some((root, path)) = path.split_once('/') {
-            if root.is_empty() {
-                return self.get(path);
+    fn lookup(
+        &self,
+        segments: &[&str],
+        params: &mut PathParams,
+        accept: &dyn Fn(&Handlers<S>) -> bool,
+    ) -> Option<&Handlers<S>> {
+        let accepted = |handlers: &Handlers<S>| !handlers.is_empty() && accept(handlers);
+        let Some((segment, rest)) = segments.split_first() else {
+            return accepted(&self.handlers).then_some(&self.handlers);
+        };
+
+        if let Ok(i) = self.nodes.binary_search_by(|n| n.key.as_str().cmp(segment)) {
+            if let Some(handlers) = self.nodes[i].lookup(rest, params, accept) {
+                return Some(handlers);
             }
+        }
 
-            let node = self.nodes.binary_search_by(|n| root.cmp(&n.key));
-            match node {
-                Ok(i) => {
-                    return self.nodes[i].get(path);
-                }
-                Err(_) => return None,
+        if let Some(ref node) = self.param {
+            if let Some(handlers) = node.lookup(rest, params, accept) {
+                params.push((node.key.clone(), segment.to_string()));
+                return Some(handlers);
             }
-        } else {
-            if path.is_empty() || path == &self.key {
-                return self.handler.clone();
+        }
+
+        if let Some(ref node) = self.catch_all {
+            if accepted(&node.handlers) {
+                params.push((node.key.clone(), segments.join("/")));
+                return Some(&node.handlers);
+            }
+        }
+
+        None
+    }
+
+    fn collect_matches<'a>(&'a self, segments: &[&str], matches: &mut Vec<&'a Handlers<S>>) {
+        let Some((segment, rest)) = segments.split_first() else {
+            if !self.handlers.is_empty() {
+                matches.push(&self.handlers);
             }
+            return;
+        };
 
-            let node = self.nodes.binary_search_by(|n| path.cmp(&n.key));
-            match node {
-                Ok(i) => return self.nodes[i].handler.clone(),
-                Err(_) => return None,
+        if let Ok(i) = self.nodes.binary_search_by(|n| n.key.as_str().cmp(segment)) {
+            self.nodes[i].collect_matches(rest, matches);
+        }
+        if let Some(ref node) = self.param {
+            node.collect_matches(rest, matches);
+        }
+        if let Some(ref node) = self.catch_all {
+            if !node.handlers.is_empty() {
+                matches.push(&node.handlers);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        error::RouteError, method::Method, response::IntoResponse, router::HandlerFn,
+        status_code::StatusCode,
+    };
+
+    use super::{decode_params, Node};
+
+    fn handler() -> HandlerFn<()> {
+        Arc::new(|_| Box::pin(async { StatusCode::Ok.into_response() }))
+    }
+
+    fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn matches(node: &Node<()>, path: &str, expected: &HandlerFn<()>) -> bool {
+        match node.get(path) {
+            Some((handlers, _)) => Arc::ptr_eq(&handlers[&Method::Get], expected),
+            None => false,
+        }
+    }
+
+    #[test]
+    fn static_routes() {
+        let mut node = Node::new("/");
+        let root = handler();
+        let b = handler();
+        let a = handler();
+        let a_c = handler();
+        node.insert("/", Method::Get, root.clone()).unwrap();
+        node.insert("/b", Method::Get, b.clone()).unwrap();
+        node.insert("/a", Method::Get, a.clone()).unwrap();
+        node.insert("/a/c", Method::Get, a_c.clone()).unwrap();
+
+        assert!(matches(&node, "/", &root));
+        assert!(matches(&node, "/a", &a));
+        assert!(matches(&node, "/a/", &a));
+        assert!(matches(&node, "/b", &b));
+        assert!(matches(&node, "/a/c", &a_c));
+        assert!(node.get("/c").is_none());
+        assert!(node.get("/a/b").is_none());
+    }
+
+    #[test]
+    fn methods_share_a_route() {
+        let mut node = Node::new("/");
+        let get = handler();
+        let post = handler();
+        node.insert("/users/:id", Method::Get, get.clone()).unwrap();
+        node.insert("/users/{id}", Method::Post, post.clone())
+            .unwrap();
+
+        let (found, _) = node.get("/users/1").unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(Arc::ptr_eq(&found[&Method::Get], &get));
+        assert!(Arc::ptr_eq(&found[&Method::Post], &post));
+    }
+
+    #[test]
+    fn method_aware_lookup() {
+        let mut node = Node::new("/");
+        let me = handler();
+        let id = handler();
+        node.insert("/users/me", Method::Get, me.clone()).unwrap();
+        node.insert("/users/:id", Method::Post, id.clone()).unwrap();
+
+        let (found, params) = node
+            .find("/users/me", |handlers| handlers.contains_key(&Method::Post))
+            .unwrap();
+        assert!(Arc::ptr_eq(&found[&Method::Post], &id));
+        assert_eq!(param(&params, "id"), Some("me"));
+        assert!(node
+            .find("/users/me", |handlers| handlers.contains_key(&Method::Put))
+            .is_none());
+        assert_eq!(node.get_all("/users/me").len(), 2);
+        assert_eq!(node.get_all("/users/42").len(), 1);
+    }
+
+    #[test]
+    fn path_params() {
+        let mut node = Node::new("/");
+        let user = handler();
+        let post = handler();
+        node.insert("/users/:id", Method::Get, user.clone())
+            .unwrap();
+        node.insert("/users/{id}/posts/{post_id}", Method::Get, post.clone())
+            .unwrap();
+
+        let (found, params) = node.get("/users/42").unwrap();
+        assert!(Arc::ptr_eq(&found[&Method::Get], &user));
+        assert_eq!(param(&params, "id"), Some("42"));
+
+        let (found, params) = node.get("/users/42/posts/7").unwrap();
+        assert!(Arc::ptr_eq(&found[&Method::Get], &post));
+        assert_eq!(
+            params,
+            [
+                ("id".to_string(), "42".to_string()),
+                ("post_id".to_string(), "7".to_string())
+            ]
+        );
+
+        assert!(node.get("/users").is_none());
+        assert!(node.get("/users/42/posts").is_none());
+    }
+
+    #[test]
+    fn static_before_dynamic() {
+        let mut node = Node::new("/");
+        let me = handler();
+        let user = handler();
+        let settings = handler();
+        node.insert("/users/:id", Method::Get, user.clone())
+            .unwrap();
+        node.insert("/users/me", Method::Get, me.clone()).unwrap();
+        node.insert("/users/:id/settings", Method::Get, settings.clone())
+            .unwrap();
+
+        assert!(matches(&node, "/users/me", &me));
+        assert!(matches(&node, "/users/other", &user));
+        // `me` has no `settings` child so the lookup falls back to the parameter
+        let (found, params) = node.get("/users/me/settings").unwrap();
+        assert!(Arc::ptr_eq(&found[&Method::Get], &settings));
+        assert_eq!(param(&params, "id"), Some("me"));
+    }
+
+    #[test]
+    fn catch_all() {
+        let mut node = Node::new("/");
+        let index = handler();
+        let assets = handler();
+        let spa = handler();
+        node.insert("/static/index.html", Method::Get, index.clone())
+            .unwrap();
+        node.insert("/static/*path", Method::Get, assets.clone())
+            .unwrap();
+        node.insert("/app/{*path}", Method::Get, spa.clone())
+            .unwrap();
+
+        assert!(matches(&node, "/static/index.html", &index));
+        let (found, params) = node.get("/static/css/main.css").unwrap();
+        assert!(Arc::ptr_eq(&found[&Method::Get], &assets));
+        assert_eq!(param(&params, "path"), Some("css/main.css"));
+
+        let (found, params) = node.get("/app/settings").unwrap();
+        assert!(Arc::ptr_eq(&found[&Method::Get], &spa));
+        assert_eq!(param(&params, "path"), Some("settings"));
+
+        assert!(node.get("/static").is_none());
+    }
+
+    #[test]
+    fn optional_segments() {
+        let mut node = Node::new("/");
+        let posts = handler();
+        node.insert("/posts/:year?/{month?}", Method::Get, posts.clone())
+            .unwrap();
+
+        let (found, params) = node.get("/posts").unwrap();
+        assert!(Arc::ptr_eq(&found[&Method::Get], &posts));
+        assert!(params.is_empty());
+
+        let (found, params) = node.get("/posts/2024").unwrap();
+        assert!(Arc::ptr_eq(&found[&Method::Get], &posts));
+        assert_eq!(param(&params, "year"), Some("2024"));
+        assert!(param(&params, "month").is_none());
+
+        let (_, params) = node.get("/posts/2024/05").unwrap();
+        assert_eq!(param(&params, "month"), Some("05"));
+
+        assert!(node.get("/posts/2024/05/01").is_none());
+    }
+
+    #[test]
+    fn invalid_routes() {
+        let mut node = Node::new("/");
+        assert!(matches!(
+            node.insert("/static/*path/edit", Method::Get, handler()),
+            Err(RouteError::CatchAllNotLast(_))
+        ));
+        assert!(matches!(
+            node.insert("/posts/:year?/archive", Method::Get, handler()),
+            Err(RouteError::OptionalNotLast(_))
+        ));
+    }
+
+    #[test]
+    fn duplicate_routes() {
+        let mut node = Node::new("/");
+        let first = handler();
+        node.insert("/users/:id", Method::Get, first.clone())
+            .unwrap();
+        node.insert("/posts/:id?", Method::Get, handler()).unwrap();
+
+        assert!(matches!(
+            node.insert("/users/{id}", Method::Get, handler()),
+            Err(RouteError::Duplicate(Method::Get, _))
+        ));
+        assert!(matches!(
+            node.insert("/posts", Method::Get, handler()),
+            Err(RouteError::Duplicate(Method::Get, _))
+        ));
+        // The optional segment conflicts with `/users/:id` so nothing is registered
+        assert!(matches!(
+            node.insert("/users/:id/:tab?", Method::Get, handler()),
+            Err(RouteError::Duplicate(Method::Get, _))
+        ));
+        assert!(node.get("/users/1/settings").is_none());
+
+        assert!(matches(&node, "/users/1", &first));
+        node.insert("/users/:id", Method::Post, handler()).unwrap();
+    }
+
+    #[test]
+    fn ambiguous_routes() {
+        let mut node = Node::new("/");
+        node.insert("/users/:id", Method::Get, handler()).unwrap();
+        node.insert("/static/*path", Method::Get, handler())
+            .unwrap();
+
+        for path in ["/users/:name", "/users/{user_id}/posts", "/users/:name?"] {
+            assert!(
+                matches!(
+                    node.insert(path, Method::Post, handler()),
+                    Err(RouteError::Ambiguous { .. })
+                ),
+                "{path}"
+            );
+        }
+        assert!(matches!(
+            node.insert("/static/{*file}", Method::Post, handler()),
+            Err(RouteError::Ambiguous { .. })
+        ));
+        assert!(node.get("/users/1/posts").is_none());
+    }
+
+    /// Calls `f` with every ordering of `items`
+    fn permutations<T: Clone>(items: &mut Vec<T>, k: usize, f: &mut impl FnMut(&[T])) {
+        if k == items.len() {
+            return f(items);
+        }
+        for i in k..items.len() {
+            items.swap(k, i);
+            permutations(items, k + 1, f);
+            items.swap(k, i);
+        }
+    }
+
+    #[test]
+    fn registration_order() {
+        let mut routes: Vec<(&str, HandlerFn<()>)> = [
+            "/users",
+            "/users/me",
+            "/users/:id",
+            "/users/:id/posts",
+            "/assets/*path",
+            "/b",
+            "/a",
+        ]
+        .into_iter()
+        .map(|path| (path, handler()))
+        .collect();
+
+        let mut count = 0;
+        permutations(&mut routes, 0, &mut |routes| {
+            let mut node = Node::new("/");
+            for (path, handler) in routes {
+                node.insert(path, Method::Get, handler.clone()).unwrap();
+            }
+            let handler = |path| &routes.iter().find(|(p, _)| *p == path).unwrap().1;
+
+            assert!(matches(&node, "/users", handler("/users")));
+            assert!(matches(&node, "/users/me", handler("/users/me")));
+            assert!(matches(&node, "/users/1", handler("/users/:id")));
+            assert!(matches(
+                &node,
+                "/users/1/posts",
+                handler("/users/:id/posts")
+            ));
+            assert!(matches(
+                &node,
+                "/assets/css/main.css",
+                handler("/assets/*path")
+            ));
+            assert!(matches(&node, "/a", handler("/a")));
+            assert!(matches(&node, "/b", handler("/b")));
+            assert!(node.get("/c").is_none());
+            count += 1;
+        });
+        assert_eq!(count, 5040);
+    }
+
+    #[test]
+    fn into_routes() {
+        let mut node = Node::new("/");
+        node.insert("/", Method::Get, handler()).unwrap();
+        node.insert("/users/{id}", Method::Get, handler()).unwrap();
+        node.insert("/users/:id", Method::Delete, handler())
+            .unwrap();
+        node.insert("/static/{*path}", Method::Get, handler())
+            .unwrap();
+
+        let mut routes: Vec<(String, Method)> = node
+            .into_routes()
+            .into_iter()
+            .map(|(path, method, _)| (path, method))
+            .collect();
+        routes.sort_by_key(|(path, method)| (path.clone(), method.to_string()));
+        assert_eq!(
+            routes,
+            [
+                ("/".to_string(), Method::Get),
+                ("/static/*path".to_string(), Method::Get),
+                ("/users/:id".to_string(), Method::Delete),
+                ("/users/:id".to_string(), Method::Get),
+            ]
+        );
+    }
+
+    #[test]
+    fn percent_decoding() {
+        let params = |values: &[&str]| -> Vec<(String, String)> {
+            values
+                .iter()
+                .map(|value| ("p".to_string(), value.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            decode_params(params(&[
+                "John%20Doe",
+                "my%20doc.txt/a%2fb",
+                "100%",
+                "%zz",
+                "%C3%A9"
+            ])),
+            Ok(params(&["John Doe", "my doc.txt/a/b", "100%", "%zz", "é"]))
+        );
+        assert_eq!(decode_params(params(&["ok", "%FF"])), Err("p".to_string()));
+    }
+}
@@ -1,24 +1,49 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashSet, future::Future, sync::Arc};
+
+use smol::future::Boxed;
 
 use crate::{
-    response::{IntoResponse, Response},
-    route_path::Node,
+    error::HttpError,
+    handler::{into_handler_fn, Handler},
+    header,
+    middleware::{self, into_middleware_fn, MiddlewareFn, Next},
+    response::{IntoResponse, Response, ResponseBuilder},
+    route_path::{self, Handlers, Node},
     status_code::StatusCode,
+    ws::{OnUpgrade, WebSocket},
 };
 
 use super::{method::Method, request::Request};
 
-pub(crate) type HandlerFn<S> = Arc<dyn Fn(Request<S>) -> Response + Send + Sync>;
+pub(crate) type HandlerFn<S> = Arc<dyn Fn(Request<S>) -> Boxed<Response> + Send + Sync>;
 
 pub struct Router<S: Clone> {
-    routes: HashMap<Method, Node<S>>,
+    routes: Node<S>,
+    /// Middleware added with [`Router::layer`], outermost last
+    layers: Vec<MiddlewareFn<S>>,
+    /// Handler for requests not matching any route, a plain 404 when not set
+    fallback: Option<HandlerFn<S>>,
+    /// Fallbacks of the nested routers, by prefix
+    nested_fallbacks: Vec<(String, HandlerFn<S>)>,
+    /// Extension methods used by at least one route, the others are not implemented
+    extension_methods: HashSet<Method>,
     state: S,
 }
 
+impl Default for Router<()> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Router<()> {
     pub fn new() -> Router<()> {
         Router {
-            routes: HashMap::new(),
+            routes: Node::new("/"),
+            layers: Vec::new(),
+            fallback: None,
+            nested_fallbacks: Vec::new(),
+            extension_methods: HashSet::new(),
             state: (),
         }
     }
@@ -27,79 +52,311 @@ impl Router<()> {
 impl<S: Clone + 'static> Router<S> {
     pub fn with_state(state: S) -> Router<S> {
         Router {
-            routes: HashMap::new(),
+            routes: Node::new("/"),
+            layers: Vec::new(),
+            fallback: None,
+            nested_fallbacks: Vec::new(),
+            extension_methods: HashSet::new(),
             state,
         }
     }
 
-    pub fn get<E: IntoResponse + 'static>(self, uri: &str, handler: fn(Request<S>) -> E) -> Self {
-        self.insert(Method::Get, uri, handler)
+    pub fn get<T, H: Handler<T, S>>(self, uri: &str, handler: H) -> Self {
+        self.route(Method::Get, uri, handler)
+    }
+
+    pub fn post<T, H: Handler<T, S>>(self, uri: &str, handler: H) -> Self {
+        self.route(Method::Post, uri, handler)
+    }
+    pub fn put<T, H: Handler<T, S>>(self, uri: &str, handler: H) -> Self {
+        self.route(Method::Put, uri, handler)
+    }
+    pub fn patch<T, H: Handler<T, S>>(self, uri: &str, handler: H) -> Self {
+        self.route(Method::Patch, uri, handler)
+    }
+    pub fn options<T, H: Handler<T, S>>(self, uri: &str, handler: H) -> Self {
+        self.route(Method::Options, uri, handler)
+    }
+    pub fn delete<T, H: Handler<T, S>>(self, uri: &str, handler: H) -> Self {
+        self.route(Method::Delete, uri, handler)
+    }
+    pub fn head<T, H: Handler<T, S>>(self, uri: &str, handler: H) -> Self {
+        self.route(Method::Head, uri, handler)
+    }
+    pub fn trace<T, H: Handler<T, S>>(self, uri: &str, handler: H) -> Self {
+        self.route(Method::Trace, uri, handler)
+    }
+    pub fn connect<T, H: Handler<T, S>>(self, uri: &str, handler: H) -> Self {
+        self.route(Method::Connect, uri, handler)
     }
 
-    pub fn post<E: IntoResponse + 'static>(self, uri: &str, handler: fn(Request<S>) -> E) -> Self {
-        self.insert(Method::Post, uri, handler)
+    /// Registers `handler` for `method` requests to `uri`, `method` can be an extension method
+    /// like `Method::Extension("PROPFIND".to_string())`. Requests with an extension method not
+    /// used by any route get a 501.
+    ///
+    /// The handler can be sync, returning anything implementing [`IntoResponse`], or async,
+    /// returning a future resolving to it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the route is invalid or a handler is already registered for `method` and `uri`.
+    pub fn route<T, H: Handler<T, S>>(self, method: Method, uri: &str, handler: H) -> Self {
+        self.insert_handler_fn(method, uri, into_handler_fn(handler))
     }
-    pub fn put<E: IntoResponse + 'static>(self, uri: &str, handler: fn(Request<S>) -> E) -> Self {
-        self.insert(Method::Put, uri, handler)
+
+    /// Adds a route answering `method` requests on `uri`, see [`Router::route`]
+    #[deprecated(note = "renamed to `Router::route`")]
+    pub fn insert<T, H: Handler<T, S>>(self, method: Method, uri: &str, handler: H) -> Self {
+        self.route(method, uri, handler)
+    }
+
+    fn insert_handler_fn(mut self, method: Method, uri: &str, handler: HandlerFn<S>) -> Self {
+        if let Method::Extension(_) = method {
+            self.extension_methods.insert(method.clone());
+        }
+        if let Err(e) = self.routes.insert(uri, method, handler) {
+            panic!("{e}");
+        }
+        self
+    }
+
+    /// Registers every route of `router` under `prefix`, e.g. its `/users` route answers
+    /// `prefix/users`.
+    ///
+    /// The routes keep the middleware added to `router`, and the state of `self` is used. The
+    /// fallback of `router`, if any, answers the requests under `prefix` not matching any route,
+    /// otherwise they get the fallback of `self`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the routes is already registered in `self`.
+    pub fn nest(mut self, prefix: &str, router: Router<S>) -> Self {
+        let prefix = prefix.trim_end_matches('/');
+        if let Some(fallback) = router.fallback.clone() {
+            let fallback = router.with_layers(fallback);
+            self.nested_fallbacks.push((prefix.to_string(), fallback));
+        }
+        for (path, fallback) in &router.nested_fallbacks {
+            let fallback = router.with_layers(fallback.clone());
+            self.nested_fallbacks
+                .push((format!("{prefix}{path}"), fallback));
+        }
+        for (path, method, handler) in router.routes.into_routes() {
+            self = self.insert_handler_fn(method, &format!("{prefix}{path}"), handler);
+        }
+        self
     }
-    pub fn patch<E: IntoResponse + 'static>(self, uri: &str, handler: fn(Request<S>) -> E) -> Self {
-        self.insert(Method::Patch, uri, handler)
+
+    /// Registers every route of `router` in `self`, as if they had been added to it directly.
+    ///
+    /// The routes keep the middleware added to `router`, and the state of `self` is used. The
+    /// fallback of `router` is kept if `self` doesn't have one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the routes is already registered in `self`, or if both routers have a
+    /// fallback.
+    pub fn merge(mut self, router: Router<S>) -> Self {
+        if let Some(fallback) = router.fallback.clone() {
+            if self.fallback.is_some() {
+                panic!("Cannot merge two routers with a fallback");
+            }
+            self.fallback = Some(router.with_layers(fallback));
+        }
+        for (path, fallback) in &router.nested_fallbacks {
+            let fallback = router.with_layers(fallback.clone());
+            self.nested_fallbacks.push((path.clone(), fallback));
+        }
+        for (path, method, handler) in router.routes.into_routes() {
+            self = self.insert_handler_fn(method, &path, handler);
+        }
+        self
     }
-    pub fn options<E: IntoResponse + 'static>(
-        self,
-        uri: &str,
-        handler: fn(Request<S>) -> E,
-    ) -> Self {
-        self.insert(Method::Options, uri, handler)
+
+    /// Answers the requests not matching any route with `handler` instead of an empty 404, e.g.
+    /// to render an HTML page or serve the `index.html` of a single page app.
+    ///
+    /// It runs inside the middleware added with [`Router::layer`] but not the one added with
+    /// [`Router::route_layer`]. Requests matching a route but not its method still get a 405.
+    pub fn fallback<T, H: Handler<T, S>>(mut self, handler: H) -> Self {
+        self.fallback = Some(into_handler_fn(handler));
+        self
     }
-    pub fn delete<E: IntoResponse + 'static>(
-        self,
-        uri: &str,
-        handler: fn(Request<S>) -> E,
-    ) -> Self {
-        self.insert(Method::Delete, uri, handler)
+
+    /// Returns the fallback for `path`, from the nested router with the longest matching prefix
+    fn fallback_for(&self, path: &str) -> Option<HandlerFn<S>> {
+        let segments: Vec<&str> = route_path::segments(path).collect();
+        self.nested_fallbacks
+            .iter()
+            .filter_map(|(prefix, fallback)| {
+                let prefix: Vec<&str> = route_path::segments(prefix).collect();
+                segments
+                    .starts_with(&prefix)
+                    .then_some((prefix.len(), fallback))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, fallback)| fallback.clone())
+            .or_else(|| self.fallback.clone())
     }
 
-    pub fn insert<E: IntoResponse + 'static>(
-        mut self,
-        method: Method,
-        uri: &str,
-        handler: fn(Request<S>) -> E,
-    ) -> Self {
-        let node = self.routes.entry(method).or_insert(Node::new("/"));
-        let handler = Arc::new(move |req| handler(req).into_response());
-        node.insert(uri, handler);
+    /// Runs `middleware` around every request, including the ones not matching any route.
+    ///
+    /// The middleware receives the request and what remains of the stack as [`Next`]. It can
+    /// answer by itself, or modify the request before passing it to [`Next::run`] and then
+    /// transform the response. It only wraps the routes added before it, and middleware added
+    /// later runs first.
+    pub fn layer<F, Fut>(mut self, middleware: F) -> Self
+    where
+        F: Fn(Request<S>, Next<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        let middleware = into_middleware_fn(middleware);
+        self.routes
+            .map_handlers(&|handler| middleware::wrap(middleware.clone(), handler));
+        self.layers.push(middleware);
+        self
+    }
 
+    /// Like [`Router::layer`] but only runs for requests matching one of the routes added before
+    /// it, so unknown paths still get a plain 404, e.g. for authentication.
+    pub fn route_layer<F, Fut>(mut self, middleware: F) -> Self
+    where
+        F: Fn(Request<S>, Next<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        let middleware = into_middleware_fn(middleware);
+        self.routes
+            .map_handlers(&|handler| middleware::wrap(middleware.clone(), handler));
         self
     }
 
-    // pub fn ws_route(mut self, uri: &str, handler: fn(WebSocket)) -> Self {
-    //     let route = Route {
-    //         methods: vec![Method::Get],
-    //         uri: uri.to_string(),
-    //     };
-    //     let handler = Box::new(move |req| {
-    //         let ws = WebSocket::from_request(req).unwrap();
-    //         handler(ws);
-    //         StatusCode::Ok.into_response()
-    //     });
-    //     self.routes.insert(route, handler);
-    //     self
-    // }
-
-    pub fn handle(&self, req: Request<S>) -> Response {
-        if let Some(node) = self.routes.get(req.method()) {
-            if let Some(handler) = node.get(req.uri()) {
-                handler(req)
-            } else {
-                StatusCode::NotFound.into_response()
+    /// Wraps a handler answering requests without a matching route in the router-wide layers
+    fn with_layers(&self, mut handler: HandlerFn<S>) -> HandlerFn<S> {
+        for layer in &self.layers {
+            handler = middleware::wrap(layer.clone(), handler);
+        }
+        handler
+    }
+
+    /// Accepts WebSocket connections on `uri`, `handler` then takes over the connection with the
+    /// handshake request, e.g. to read its path parameters or state.
+    ///
+    /// Handshakes for another version than 13 get a 426 listing the supported version, other
+    /// invalid ones a 400. With the `deflate` feature, the first valid `permessage-deflate` offer
+    /// is accepted, and messages are then compressed transparently.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the route is invalid or a GET handler is already registered for `uri`.
+    pub fn ws<F, Fut>(self, uri: &str, handler: F) -> Self
+    where
+        S: Send,
+        F: Fn(Request<S>, WebSocket) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let handler: HandlerFn<S> = Arc::new(move |req| {
+            let (mut res, extensions) = match WebSocket::accept(&req) {
+                Ok(accepted) => accepted,
+                Err(e) => return Box::pin(async move { e.into_response() }),
+            };
+            let handler = handler.clone();
+            res.upgrade = Some(OnUpgrade::new(extensions, move |ws| handler(req, ws)));
+            Box::pin(async move { res })
+        });
+        self.insert_handler_fn(Method::Get, uri, handler)
+    }
+
+    pub async fn handle(&self, mut req: Request<S>) -> Response {
+        if let Method::Extension(_) = req.method() {
+            if !self.extension_methods.contains(req.method()) {
+                let method = req.method().to_string();
+                let handler = self.with_layers(Arc::new(move |_| {
+                    let res = HttpError::UnknownMethod(method.clone()).into_response();
+                    Box::pin(async move { res })
+                }));
+                return handler(req).await;
             }
-        } else {
-            StatusCode::NotFound.into_response()
         }
+
+        let method = req.method().clone();
+        let found = self.routes.find(req.uri(), |handlers| {
+            handler_for(handlers, &method).is_some()
+        });
+        let handler = match found {
+            Some((handlers, params)) => match route_path::decode_params(params) {
+                Ok(params) => {
+                    let handler = handler_for(handlers, &method).unwrap().clone();
+                    req.set_path_params(params);
+                    handler
+                }
+                Err(name) => self.with_layers(Arc::new(move |_| {
+                    let res = HttpError::InvalidPathParam(name.clone()).into_response();
+                    Box::pin(async move { res })
+                })),
+            },
+            None => {
+                let matches = self.routes.get_all(req.uri());
+                if matches.is_empty() {
+                    match self.fallback_for(req.uri()) {
+                        Some(fallback) => self.with_layers(fallback),
+                        None => self.with_layers(Arc::new(|_| {
+                            Box::pin(async { StatusCode::NotFound.into_response() })
+                        })),
+                    }
+                } else {
+                    // OPTIONS requests get the allowed methods unless a handler is registered
+                    let status_code = match method {
+                        Method::Options => StatusCode::Ok,
+                        _ => StatusCode::MethodNotAllowed,
+                    };
+                    let allowed = allowed_methods(&matches);
+                    self.with_layers(Arc::new(move |_| {
+                        let res = ResponseBuilder::new()
+                            .with_status_code(status_code)
+                            .append_header(header::ALLOW, allowed.as_str())
+                            .build();
+                        Box::pin(async move { res })
+                    }))
+                }
+            }
+        };
+
+        handler(req).await
     }
 
     pub fn state(&self) -> &S {
         &self.state
     }
 }
+
+/// Returns the handler of `method` among `handlers`, HEAD requests being answered by the GET
+/// handler whose body is stripped when sending the response
+fn handler_for<'a, S: Clone>(
+    handlers: &'a Handlers<S>,
+    method: &Method,
+) -> Option<&'a HandlerFn<S>> {
+    handlers.get(method).or_else(|| match method {
+        Method::Head => handlers.get(&Method::Get),
+        _ => None,
+    })
+}
+
+/// Returns the value of the `Allow` header for a path matching routes with `matches`
+fn allowed_methods<S: Clone>(matches: &[&Handlers<S>]) -> String {
+    let methods: HashSet<&Method> = matches
+        .iter()
+        .flat_map(|handlers| handlers.keys())
+        .collect();
+    let mut allowed: Vec<String> = methods.iter().map(|method| method.to_string()).collect();
+    if methods.contains(&Method::Get) && !methods.contains(&Method::Head) {
+        allowed.push(Method::Head.to_string());
+    }
+    if !methods.contains(&Method::Options) {
+        allowed.push(Method::Options.to_string());
+    }
+    allowed.sort();
+    allowed.join(", ")
+}
@@ -1,30 +1,178 @@
-use crate::response::{IntoResponse, Response, ResponseBuilder};
+use crate::{
+    error::InvalidStatusCode,
+    response::{IntoResponse, Response, ResponseBuilder},
+};
 
-#[derive(Debug, Clone)]
-pub enum StatusCode {
-    Ok = 200,
-    BadRequest = 400,
-    Forbidden = 403,
-    NotFound = 404,
-    MethodNotAllowed = 405,
-    LengthRequired = 411,
-    UnsupportedMediaType = 415,
-    InternalServerError = 500,
+macro_rules! status_codes {
+    ($($(#[$doc:meta])* $name:ident = $code:literal, $reason:literal;)*) => {
+        /// A response status code, one of the IANA registry or any other code between 100 and 599
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum StatusCode {
+            $($(#[$doc])* $name,)*
+            /// A code not in the registry, built with [`StatusCode::try_from`]
+            Custom(CustomCode),
+        }
+
+        impl StatusCode {
+            pub fn as_u16(&self) -> u16 {
+                match self {
+                    $(Self::$name => $code,)*
+                    Self::Custom(code) => code.as_u16(),
+                }
+            }
+
+            /// Returns the reason phrase of the code, `None` for codes not in the registry
+            pub fn reason_phrase(&self) -> Option<&'static str> {
+                match self {
+                    $(Self::$name => Some($reason),)*
+                    Self::Custom(_) => None,
+                }
+            }
+
+            fn registered(code: u16) -> Option<Self> {
+                match code {
+                    $($code => Some(Self::$name),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Processing = 102, "Processing";
+    EarlyHints = 103, "Early Hints";
+
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NonAuthoritativeInformation = 203, "Non-Authoritative Information";
+    NoContent = 204, "No Content";
+    ResetContent = 205, "Reset Content";
+    PartialContent = 206, "Partial Content";
+    MultiStatus = 207, "Multi-Status";
+    AlreadyReported = 208, "Already Reported";
+    ImUsed = 226, "IM Used";
+
+    MultipleChoices = 300, "Multiple Choices";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    UseProxy = 305, "Use Proxy";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    PaymentRequired = 402, "Payment Required";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    ContentTooLarge = 413, "Content Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    MisdirectedRequest = 421, "Misdirected Request";
+    UnprocessableContent = 422, "Unprocessable Content";
+    Locked = 423, "Locked";
+    FailedDependency = 424, "Failed Dependency";
+    TooEarly = 425, "Too Early";
+    UpgradeRequired = 426, "Upgrade Required";
+    PreconditionRequired = 428, "Precondition Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    UnavailableForLegalReasons = 451, "Unavailable For Legal Reasons";
+
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+    VariantAlsoNegotiates = 506, "Variant Also Negotiates";
+    InsufficientStorage = 507, "Insufficient Storage";
+    LoopDetected = 508, "Loop Detected";
+    NotExtended = 510, "Not Extended";
+    NetworkAuthenticationRequired = 511, "Network Authentication Required";
+}
+
+/// A code between 100 and 599 which isn't in the registry, so every code has a single representation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomCode(u16);
+
+impl CustomCode {
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+}
+
+impl StatusCode {
+    /// 1xx
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.as_u16())
+    }
+
+    /// 2xx
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.as_u16())
+    }
+
+    /// 3xx
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.as_u16())
+    }
+
+    /// 4xx
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.as_u16())
+    }
+
+    /// 5xx
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.as_u16())
+    }
+}
+
+impl TryFrom<u16> for StatusCode {
+    type Error = InvalidStatusCode;
+
+    /// Returns the registered variant for `code`, or [`StatusCode::Custom`] for other codes between
+    /// 100 and 599
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        match Self::registered(code) {
+            Some(status_code) => Ok(status_code),
+            None if (100..=599).contains(&code) => Ok(Self::Custom(CustomCode(code))),
+            None => Err(InvalidStatusCode(code)),
+        }
+    }
+}
+
+impl From<StatusCode> for u16 {
+    fn from(status_code: StatusCode) -> Self {
+        status_code.as_u16()
+    }
 }
 
 impl std::fmt::Display for StatusCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let status_text = match self {
-            StatusCode::Ok => "OK",
-            StatusCode::BadRequest => "BAD REQUEST",
-            StatusCode::Forbidden => "FORBIDDEN",
-            StatusCode::NotFound => "NOT FOUND",
-            StatusCode::MethodNotAllowed => "METHOD NOT ALLOWED",
-            StatusCode::LengthRequired => "LENGTH REQUIRED",
-            StatusCode::UnsupportedMediaType => "UNSUPPORTED MEDIA TYPE",
-            StatusCode::InternalServerError => "INTERNAL SERVER ERROR",
-        };
-        write!(f, "{} {}", self.clone() as u16, status_text)
+        // The reason phrase can be empty but the space before it is still required
+        write!(
+            f,
+            "{} {}",
+            self.as_u16(),
+            self.reason_phrase().unwrap_or_default()
+        )
     }
 }
 
@@ -33,3 +181,48 @@ impl IntoResponse for StatusCode {
         ResponseBuilder::new().with_status_code(self).build()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::StatusCode;
+
+    #[test]
+    fn display() {
+        assert_eq!(StatusCode::Ok.to_string(), "200 OK");
+        assert_eq!(StatusCode::NotFound.to_string(), "404 Not Found");
+        assert_eq!(
+            StatusCode::HttpVersionNotSupported.to_string(),
+            "505 HTTP Version Not Supported"
+        );
+        assert_eq!(StatusCode::try_from(599).unwrap().to_string(), "599 ");
+    }
+
+    #[test]
+    fn try_from_u16() {
+        assert!(matches!(StatusCode::try_from(201), Ok(StatusCode::Created)));
+        assert!(matches!(
+            StatusCode::try_from(308),
+            Ok(StatusCode::PermanentRedirect)
+        ));
+        assert!(matches!(
+            StatusCode::try_from(299),
+            Ok(StatusCode::Custom(code)) if code.as_u16() == 299
+        ));
+        assert!(StatusCode::try_from(99).is_err());
+        assert!(StatusCode::try_from(600).is_err());
+        assert!(StatusCode::try_from(999).is_err());
+        assert_eq!(u16::from(StatusCode::TooManyRequests), 429);
+        // Registered codes are never custom ones
+        assert_eq!(StatusCode::try_from(404).unwrap(), StatusCode::NotFound);
+    }
+
+    #[test]
+    fn classes() {
+        assert!(StatusCode::Continue.is_informational());
+        assert!(StatusCode::NoContent.is_success());
+        assert!(StatusCode::SeeOther.is_redirection());
+        assert!(StatusCode::Conflict.is_client_error());
+        assert!(StatusCode::ServiceUnavailable.is_server_error());
+        assert!(StatusCode::try_from(600).is_err());
+    }
+}
@@ -1,26 +1,609 @@
-use std::sync::mpsc::{Receiver, Sender};
+use std::{future::Future, sync::Arc};
 
-use crate::{header, request::Request, HttpResult};
+use smol::{
+    future::Boxed,
+    io::{AsyncWriteExt, BufReader},
+    lock::Mutex,
+    net::TcpStream,
+};
 
+use crate::{
+    base64,
+    error::{HttpError, WebSocketError},
+    header::{self, HeaderName},
+    http_version::HttpVersion,
+    method::Method,
+    request::Request,
+    response::{Response, ResponseBuilder},
+    sha1::sha1,
+    status_code::StatusCode,
+    HttpResult,
+};
+
+#[cfg(feature = "deflate")]
+mod deflate;
+mod frame;
+
+/// Without the `deflate` feature, permessage-deflate is never negotiated
+#[cfg(not(feature = "deflate"))]
+mod deflate {
+    use crate::error::WebSocketError;
+
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) enum DeflateParams {}
+
+    impl DeflateParams {
+        pub(crate) fn negotiate<'a>(_: impl Iterator<Item = &'a str>) -> Option<Self> {
+            None
+        }
+    }
+
+    impl std::fmt::Display for DeflateParams {
+        fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match *self {}
+        }
+    }
+
+    pub(crate) enum Deflate {}
+
+    impl Deflate {
+        pub(crate) fn new(params: DeflateParams) -> Self {
+            match params {}
+        }
+
+        pub(crate) fn compress(&mut self, _: &[u8]) -> Result<Vec<u8>, WebSocketError> {
+            match *self {}
+        }
+
+        pub(crate) fn decompress(
+            &mut self,
+            _: Vec<u8>,
+            _: usize,
+        ) -> Result<Vec<u8>, WebSocketError> {
+            match *self {}
+        }
+    }
+}
+
+use deflate::{Deflate, DeflateParams};
+use frame::{Frame, OpCode, MAX_CONTROL_PAYLOAD};
+
+/// Appended to the key of the client before hashing it, as defined by RFC 6455
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// A message sent or received on a [`WebSocket`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Received pings are answered automatically, they are only passed on for information
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// Starts or answers the close handshake. A received close is the last message, and is
+    /// answered automatically if no close was sent before.
+    Close(Option<CloseFrame>),
+}
+
+/// The status code and reason of a close message
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseFrame {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+/// The status code of a close message, as defined by RFC 6455
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    /// 1000
+    Normal,
+    /// 1001, e.g. the server is going down or a browser left the page
+    GoingAway,
+    /// 1002
+    ProtocolError,
+    /// 1003, e.g. binary messages sent to an endpoint only accepting text
+    UnsupportedData,
+    /// 1007, e.g. a text message that isn't UTF-8
+    InvalidPayload,
+    /// 1008
+    PolicyViolation,
+    /// 1009
+    MessageTooBig,
+    /// 1010, the client expected an extension the server didn't negotiate
+    MandatoryExtension,
+    /// 1011
+    InternalError,
+    /// Another code, e.g. an application-specific one between 4000 and 4999
+    Other(u16),
+}
+
+impl From<u16> for CloseCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1000 => Self::Normal,
+            1001 => Self::GoingAway,
+            1002 => Self::ProtocolError,
+            1003 => Self::UnsupportedData,
+            1007 => Self::InvalidPayload,
+            1008 => Self::PolicyViolation,
+            1009 => Self::MessageTooBig,
+            1010 => Self::MandatoryExtension,
+            1011 => Self::InternalError,
+            code => Self::Other(code),
+        }
+    }
+}
+
+impl From<CloseCode> for u16 {
+    fn from(code: CloseCode) -> Self {
+        match code {
+            CloseCode::Normal => 1000,
+            CloseCode::GoingAway => 1001,
+            CloseCode::ProtocolError => 1002,
+            CloseCode::UnsupportedData => 1003,
+            CloseCode::InvalidPayload => 1007,
+            CloseCode::PolicyViolation => 1008,
+            CloseCode::MessageTooBig => 1009,
+            CloseCode::MandatoryExtension => 1010,
+            CloseCode::InternalError => 1011,
+            CloseCode::Other(code) => code,
+        }
+    }
+}
+
+impl CloseFrame {
+    /// Returns `None` for a close message without payload
+    fn parse(payload: &[u8]) -> Result<Option<Self>, WebSocketError> {
+        let (code, reason) = match payload {
+            [] => return Ok(None),
+            [a, b, reason @ ..] => (u16::from_be_bytes([*a, *b]), reason),
+            _ => return Err(WebSocketError::Protocol("Truncated close code")),
+        };
+        // Other codes are reserved, or can't be sent in a close frame like 1005 and 1006
+        if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+            return Err(WebSocketError::Protocol("Invalid close code"));
+        }
+        let reason = std::str::from_utf8(reason).map_err(|_| WebSocketError::InvalidUtf8)?;
+        Ok(Some(Self {
+            code: code.into(),
+            reason: reason.to_string(),
+        }))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut payload = u16::from(self.code).to_be_bytes().to_vec();
+        payload.extend_from_slice(self.reason.as_bytes());
+        payload
+    }
+}
+
+/// The connection of a request upgraded to the WebSocket protocol, passed to the handler
+/// registered with [`Router::ws`](crate::router::Router::ws) once the handshake is complete.
+///
+/// Messages are exchanged with [`WebSocket::send`] and [`WebSocket::recv`]. To send while waiting
+/// for a message, e.g. from another task, [`WebSocket::split`] the connection. A protocol error of
+/// the client, e.g. a message bigger than
+/// [`ServerConfig::with_max_websocket_message_size`](crate::config::ServerConfig::with_max_websocket_message_size),
+/// closes the connection with the matching close code.
 pub struct WebSocket {
-    sender: Sender<String>,
-    receiver: Receiver<String>,
+    reader: WebSocketReader,
+    writer: WebSocketWriter,
+}
+
+/// The receiving half of a [`WebSocket`], it still answers pings and close messages
+pub struct WebSocketReader {
+    stream: BufReader<TcpStream>,
+    max_message_size: usize,
+    /// Opcode, whether it is compressed and payload received so far of a fragmented message
+    fragmented: Option<(OpCode, bool, Vec<u8>)>,
+    writer: WebSocketWriter,
+}
+
+/// The sending half of a [`WebSocket`], cloned to send from several tasks
+#[derive(Clone)]
+pub struct WebSocketWriter {
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// State of the connection used by both halves
+struct Shared {
+    stream: TcpStream,
+    deflate: Option<Deflate>,
+    close_sent: bool,
+    /// Whether the close handshake is done or the connection failed
+    closed: bool,
+}
+
+/// Extensions negotiated in the handshake
+#[derive(Debug, Default)]
+pub(crate) struct Extensions {
+    deflate: Option<DeflateParams>,
 }
 
 impl WebSocket {
-    pub fn split(self) -> (Sender<String>, Receiver<String>) {
-        (self.sender, self.receiver)
+    /// Checks that `req` is a valid opening handshake and returns the `101 Switching Protocols`
+    /// response accepting it, along with the negotiated extensions.
+    ///
+    /// Handshakes for another version than 13 get a 426 listing the supported version, other
+    /// invalid ones a 400. With the `deflate` feature, the first valid `permessage-deflate` offer
+    /// is accepted, and messages are then compressed transparently.
+    pub(crate) fn accept<S: Clone>(req: &Request<S>) -> HttpResult<(Response, Extensions)> {
+        let invalid = |reason: &str| Err(HttpError::InvalidWebSocketHandshake(reason.to_string()));
+        let has_token = |name: HeaderName, token: &str| {
+            req.headers()
+                .get_all(name)
+                .flat_map(|value| value.as_str().split(','))
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        };
+
+        if *req.method() != Method::Get || *req.http_version() != HttpVersion::HTTP1_1 {
+            return invalid("Not an HTTP/1.1 GET request");
+        }
+        if !req.headers().contains_key(header::HOST) {
+            return invalid("Host missing");
+        }
+        if !has_token(header::UPGRADE, "websocket") {
+            return invalid("Upgrade is not websocket");
+        }
+        if !has_token(header::CONNECTION, "upgrade") {
+            return invalid("Connection is not upgrade");
+        }
+        let Some(version) = req.headers().get(header::SEC_WEBSOCKET_VERSION) else {
+            return invalid("Sec-WebSocket-Version missing");
+        };
+        if version.as_str().trim() != "13" {
+            return Err(HttpError::UnsupportedWebSocketVersion(version.to_string()));
+        }
+        let Some(key) = req.headers().get(header::SEC_WEBSOCKET_KEY) else {
+            return invalid("Sec-WebSocket-Key missing");
+        };
+        // The key is 16 random bytes encoded in base64
+        let key = key.as_str().trim();
+        if base64::decode(key).map(|key| key.len()) != Some(16) {
+            return invalid("Invalid Sec-WebSocket-Key");
+        }
+
+        let accept = base64::encode(&sha1(format!("{key}{GUID}").as_bytes()));
+        let mut res = ResponseBuilder::new()
+            .with_status_code(StatusCode::SwitchingProtocols)
+            .append_header(header::UPGRADE, "websocket")
+            .append_header(header::CONNECTION, "Upgrade")
+            .append_header(header::SEC_WEBSOCKET_ACCEPT, accept);
+        let extensions = Extensions {
+            deflate: DeflateParams::negotiate(
+                req.headers()
+                    .get_all(header::SEC_WEBSOCKET_EXTENSIONS)
+                    .map(|value| value.as_str()),
+            ),
+        };
+        if let Some(params) = extensions.deflate {
+            res = res.append_header(header::SEC_WEBSOCKET_EXTENSIONS, params.to_string());
+        }
+        Ok((res.build(), extensions))
+    }
+
+    fn new(
+        stream: BufReader<TcpStream>,
+        max_message_size: usize,
+        deflate: Option<Deflate>,
+    ) -> Self {
+        let writer = WebSocketWriter {
+            shared: Arc::new(Mutex::new(Shared {
+                stream: stream.get_ref().clone(),
+                deflate,
+                close_sent: false,
+                closed: false,
+            })),
+        };
+        Self {
+            reader: WebSocketReader {
+                stream,
+                max_message_size,
+                fragmented: None,
+                writer: writer.clone(),
+            },
+            writer,
+        }
+    }
+
+    /// Receives the next message, see [`WebSocketReader::recv`]
+    pub async fn recv(&mut self) -> Result<Message, WebSocketError> {
+        self.reader.recv().await
+    }
+
+    /// Sends `message`, see [`WebSocketWriter::send`]
+    pub async fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        self.writer.send(message).await
+    }
+
+    /// Splits the connection into halves sharing its state, so messages can be sent while
+    /// waiting for the next one. A close received by the reader also closes the writer.
+    pub fn split(self) -> (WebSocketReader, WebSocketWriter) {
+        (self.reader, self.writer)
+    }
+}
+
+impl WebSocketReader {
+    /// Receives the next message, joining fragmented ones.
+    ///
+    /// Returns [`WebSocketError::ConnectionClosed`] once a close message was received or after an
+    /// error.
+    pub async fn recv(&mut self) -> Result<Message, WebSocketError> {
+        if self.writer.shared.lock().await.closed {
+            return Err(WebSocketError::ConnectionClosed);
+        }
+        let result = self.read_message().await;
+        if let Err(e) = &result {
+            let mut shared = self.writer.shared.lock().await;
+            shared.closed = true;
+            let code = match e {
+                WebSocketError::Protocol(_) => CloseCode::ProtocolError,
+                WebSocketError::InvalidUtf8 => CloseCode::InvalidPayload,
+                WebSocketError::MessageTooBig => CloseCode::MessageTooBig,
+                WebSocketError::Io(_) | WebSocketError::ConnectionClosed => return result,
+            };
+            if !shared.close_sent {
+                let close = CloseFrame {
+                    code,
+                    reason: String::new(),
+                };
+                // The connection is dropped anyway, the error of the client matters more
+                let _ = shared
+                    .write_frame(Frame::new(OpCode::Close, close.encode()))
+                    .await;
+            }
+        }
+        result
+    }
+
+    async fn read_message(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            let received = self
+                .fragmented
+                .as_ref()
+                .map_or(0, |(_, _, payload)| payload.len());
+            let frame = Frame::read(&mut self.stream, self.max_message_size - received).await?;
+            let compressed = self.is_compressed(&frame).await?;
+            match frame.opcode {
+                OpCode::Ping => {
+                    let mut shared = self.writer.shared.lock().await;
+                    if !shared.close_sent {
+                        let pong = Frame::new(OpCode::Pong, frame.payload.clone());
+                        shared.write_frame(pong).await?;
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                }
+                OpCode::Pong => return Ok(Message::Pong(frame.payload)),
+                OpCode::Close => {
+                    let close = CloseFrame::parse(&frame.payload)?;
+                    let mut shared = self.writer.shared.lock().await;
+                    if !shared.close_sent {
+                        shared.close_sent = true;
+                        // Echoes the status code, as recommended by RFC 6455
+                        let payload = close
+                            .as_ref()
+                            .map(|close| u16::from(close.code).to_be_bytes().to_vec())
+                            .unwrap_or_default();
+                        shared
+                            .write_frame(Frame::new(OpCode::Close, payload))
+                            .await?;
+                    }
+                    shared.closed = true;
+                    return Ok(Message::Close(close));
+                }
+                OpCode::Text | OpCode::Binary => {
+                    if self.fragmented.is_some() {
+                        return Err(WebSocketError::Protocol(
+                            "New message before the end of a fragmented one",
+                        ));
+                    }
+                    if frame.fin {
+                        return self.message(frame.opcode, compressed, frame.payload).await;
+                    }
+                    self.fragmented = Some((frame.opcode, compressed, frame.payload));
+                }
+                OpCode::Continuation => {
+                    let Some((_, _, payload)) = &mut self.fragmented else {
+                        return Err(WebSocketError::Protocol(
+                            "Continuation frame without a fragmented message",
+                        ));
+                    };
+                    payload.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        let (opcode, compressed, payload) = self.fragmented.take().unwrap();
+                        return self.message(opcode, compressed, payload).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns whether `frame` starts a compressed message, only possible once permessage-deflate
+    /// is negotiated
+    async fn is_compressed(&self, frame: &Frame) -> Result<bool, WebSocketError> {
+        if !frame.rsv1 {
+            return Ok(false);
+        }
+        let negotiated = self.writer.shared.lock().await.deflate.is_some();
+        if negotiated && matches!(frame.opcode, OpCode::Text | OpCode::Binary) {
+            return Ok(true);
+        }
+        Err(WebSocketError::Protocol("Reserved bits set"))
+    }
+
+    async fn message(
+        &self,
+        opcode: OpCode,
+        compressed: bool,
+        mut payload: Vec<u8>,
+    ) -> Result<Message, WebSocketError> {
+        if compressed {
+            let mut shared = self.writer.shared.lock().await;
+            // Only set once permessage-deflate is negotiated
+            let deflate = shared.deflate.as_mut().unwrap();
+            payload = deflate.decompress(payload, self.max_message_size)?;
+        }
+        match opcode {
+            OpCode::Text => String::from_utf8(payload)
+                .map(Message::Text)
+                .map_err(|_| WebSocketError::InvalidUtf8),
+            _ => Ok(Message::Binary(payload)),
+        }
     }
+}
 
-    pub fn from_request<S: Clone>(req: Request<S>) -> HttpResult<Self> {
-        let Some(origin) = req.headers().get(header::ORIGIN) else {
-            todo!("Continue WebSocket handling");
-            //return Err();
+impl WebSocketWriter {
+    /// Sends `message`, nothing can be sent after a close message.
+    ///
+    /// Returns [`WebSocketError::Protocol`] for a ping, pong or close payload longer than 125
+    /// bytes.
+    pub async fn send(&self, message: Message) -> Result<(), WebSocketError> {
+        let mut shared = self.shared.lock().await;
+        if shared.close_sent || shared.closed {
+            return Err(WebSocketError::ConnectionClosed);
+        }
+        let mut frame = match message {
+            Message::Text(text) => Frame::new(OpCode::Text, text.into_bytes()),
+            Message::Binary(data) => Frame::new(OpCode::Binary, data),
+            Message::Ping(data) => Frame::new(OpCode::Ping, data),
+            Message::Pong(data) => Frame::new(OpCode::Pong, data),
+            Message::Close(close) => Frame::new(
+                OpCode::Close,
+                close.map(|close| close.encode()).unwrap_or_default(),
+            ),
         };
-        let sec_websocket_version = req.headers().get(header::SEC_WEBSOCKET_VERSION);
-        let sec_websocket_key = req.headers().get(header::SEC_WEBSOCKET_KEY);
-        let sec_websocket_accept = req.headers().get(header::SEC_WEBSOCKET_ACCEPT);
-        let connection = req.headers().get(header::CONNECTION);
-        todo!("Continue WebSocket handling");
+        if frame.opcode.is_control() && frame.payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(WebSocketError::Protocol(
+                "Control message longer than 125 bytes",
+            ));
+        }
+        if frame.opcode == OpCode::Close {
+            shared.close_sent = true;
+        }
+        if let Some(deflate) = &mut shared.deflate {
+            if !frame.opcode.is_control() {
+                frame.payload = deflate.compress(&frame.payload)?;
+                frame.rsv1 = true;
+            }
+        }
+        shared.write_frame(frame).await
+    }
+}
+
+impl Shared {
+    async fn write_frame(&mut self, frame: Frame) -> Result<(), WebSocketError> {
+        self.stream.write_all(&frame.encode()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// Takes over the connection once a `101 Switching Protocols` response has been sent
+pub(crate) struct OnUpgrade {
+    extensions: Extensions,
+    handler: Box<dyn FnOnce(WebSocket) -> Boxed<()> + Send>,
+}
+
+impl OnUpgrade {
+    pub(crate) fn new<F, Fut>(extensions: Extensions, f: F) -> Self
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Self {
+            extensions,
+            handler: Box::new(move |ws| Box::pin(f(ws))),
+        }
+    }
+
+    pub(crate) async fn run(self, stream: BufReader<TcpStream>, max_message_size: usize) {
+        let deflate = self.extensions.deflate.map(Deflate::new);
+        (self.handler)(WebSocket::new(stream, max_message_size, deflate)).await
+    }
+}
+
+impl std::fmt::Debug for OnUpgrade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "OnUpgrade")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::{
+        error::{HttpError, WebSocketError},
+        request::Request,
+        status_code::StatusCode,
+    };
+
+    use super::{CloseCode, CloseFrame, WebSocket};
+
+    fn handshake(headers: &str) -> Result<crate::response::Response, HttpError> {
+        let raw = format!("GET /chat HTTP/1.1\r\nHost: example.com\r\n{headers}\r\n");
+        let peer_addr: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let req = smol::block_on(Request::parse(&mut raw.as_bytes(), peer_addr, ())).unwrap();
+        WebSocket::accept(&req).map(|(res, _)| res)
+    }
+
+    const VALID: &str = "Upgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+                         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                         Sec-WebSocket-Version: 13\r\n";
+
+    #[test]
+    fn accept_key() {
+        let res = handshake(VALID).unwrap();
+        assert_eq!(res.status_code, StatusCode::SwitchingProtocols);
+        // Example of RFC 6455
+        assert_eq!(
+            res.headers.get("sec-websocket-accept").unwrap(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(res.headers.get("upgrade").unwrap(), "websocket");
+    }
+
+    #[test]
+    fn invalid_handshakes() {
+        for headers in [
+            VALID.replace("Upgrade: websocket", "Upgrade: h2c"),
+            VALID.replace("keep-alive, Upgrade", "keep-alive"),
+            VALID.replace("dGhlIHNhbXBsZSBub25jZQ==", "c2hvcnQ="),
+            VALID.replace("Sec-WebSocket-Version: 13\r\n", ""),
+        ] {
+            assert!(
+                matches!(
+                    handshake(&headers),
+                    Err(HttpError::InvalidWebSocketHandshake(_))
+                ),
+                "{headers:?}"
+            );
+        }
+        assert!(matches!(
+            handshake(&VALID.replace("Version: 13", "Version: 8")),
+            Err(HttpError::UnsupportedWebSocketVersion(version)) if version == "8"
+        ));
+    }
+
+    #[test]
+    fn close_frames() {
+        assert_eq!(CloseFrame::parse(&[]).unwrap(), None);
+        let close = CloseFrame::parse(b"\x03\xE8bye").unwrap().unwrap();
+        assert_eq!(close.code, CloseCode::Normal);
+        assert_eq!(close.reason, "bye");
+        assert_eq!(close.encode(), b"\x03\xE8bye");
+        let close = CloseFrame::parse(&[0x0F, 0xA0]).unwrap().unwrap();
+        assert_eq!(close.code, CloseCode::Other(4000));
+
+        // Truncated, reserved and local-only codes
+        for payload in [&[0x03][..], &[0x03, 0xEC], &[0x03, 0xED], &[0x0B, 0xB7]] {
+            assert!(
+                matches!(CloseFrame::parse(payload), Err(WebSocketError::Protocol(_))),
+                "{payload:?}"
+            );
+        }
+        assert!(matches!(
+            CloseFrame::parse(&[0x03, 0xE8, 0xFF]),
+            Err(WebSocketError::InvalidUtf8)
+        ));
     }
 }
@@ -0,0 +1,275 @@
+//! The `permessage-deflate` extension, as defined by RFC 7692
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use crate::error::WebSocketError;
+
+/// Ends the payload of a compressed message once the client removed it
+const TAIL: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+/// Parameters of a `permessage-deflate` offer accepted in the handshake
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct DeflateParams {
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+    server_max_window_bits: Option<u8>,
+}
+
+impl DeflateParams {
+    /// Returns the first acceptable offer among the values of `Sec-WebSocket-Extensions`
+    pub(crate) fn negotiate<'a>(extensions: impl Iterator<Item = &'a str>) -> Option<Self> {
+        extensions
+            .flat_map(|value| value.split(','))
+            .find_map(Self::parse_offer)
+    }
+
+    /// Returns `None` for another extension, or an offer with unknown, repeated or invalid
+    /// parameters, which must be declined
+    fn parse_offer(offer: &str) -> Option<Self> {
+        let mut params = offer.split(';').map(str::trim);
+        if params.next()? != "permessage-deflate" {
+            return None;
+        }
+
+        let mut accepted = Self::default();
+        let mut client_max_window_bits = false;
+        for param in params {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+            let window_bits = value.and_then(|value| value.parse::<u8>().ok());
+            match (name, value) {
+                ("server_no_context_takeover", None) if !accepted.server_no_context_takeover => {
+                    accepted.server_no_context_takeover = true;
+                }
+                ("client_no_context_takeover", None) if !accepted.client_no_context_takeover => {
+                    accepted.client_no_context_takeover = true;
+                }
+                // zlib can't compress with a window of 8 bits, so such offers are declined
+                ("server_max_window_bits", Some(_))
+                    if accepted.server_max_window_bits.is_none()
+                        && matches!(window_bits, Some(9..=15)) =>
+                {
+                    accepted.server_max_window_bits = window_bits;
+                }
+                // Messages of the client are decompressed with the biggest window, so whatever
+                // window it uses is fine
+                ("client_max_window_bits", None) if !client_max_window_bits => {
+                    client_max_window_bits = true;
+                }
+                ("client_max_window_bits", Some(_))
+                    if !client_max_window_bits && matches!(window_bits, Some(8..=15)) =>
+                {
+                    client_max_window_bits = true;
+                }
+                _ => return None,
+            }
+        }
+        Some(accepted)
+    }
+}
+
+/// The `Sec-WebSocket-Extensions` value of the response
+impl std::fmt::Display for DeflateParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "permessage-deflate")?;
+        if self.server_no_context_takeover {
+            write!(f, "; server_no_context_takeover")?;
+        }
+        if self.client_no_context_takeover {
+            write!(f, "; client_no_context_takeover")?;
+        }
+        if let Some(window_bits) = self.server_max_window_bits {
+            write!(f, "; server_max_window_bits={window_bits}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compression state of a connection, the window of previous messages is kept unless the
+/// handshake disabled context takeover
+pub(crate) struct Deflate {
+    params: DeflateParams,
+    compress: Compress,
+    decompress: Decompress,
+}
+
+impl Deflate {
+    pub(crate) fn new(params: DeflateParams) -> Self {
+        let window_bits = params.server_max_window_bits.unwrap_or(15);
+        Self {
+            params,
+            compress: Compress::new_with_window_bits(Compression::default(), false, window_bits),
+            decompress: Decompress::new_with_window_bits(false, 15),
+        }
+    }
+
+    pub(crate) fn compress(&mut self, payload: &[u8]) -> Result<Vec<u8>, WebSocketError> {
+        let mut compressed = Vec::with_capacity(payload.len() / 2 + 64);
+        let start = self.compress.total_in();
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            self.compress
+                .compress_vec(&payload[consumed..], &mut compressed, FlushCompress::Sync)
+                .map_err(|e| WebSocketError::Io(std::io::Error::other(e)))?;
+            // The flush is complete once the output isn't limited by the buffer
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == payload.len() && compressed.len() < compressed.capacity() {
+                break;
+            }
+            compressed.reserve(compressed.capacity());
+        }
+        // The flush ends with an empty block, which the client adds back
+        if compressed.ends_with(&TAIL) {
+            compressed.truncate(compressed.len() - TAIL.len());
+        }
+        if self.params.server_no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(compressed)
+    }
+
+    /// Returns [`WebSocketError::MessageTooBig`] as soon as the message is bigger than `max_size`
+    /// once decompressed
+    pub(crate) fn decompress(
+        &mut self,
+        mut payload: Vec<u8>,
+        max_size: usize,
+    ) -> Result<Vec<u8>, WebSocketError> {
+        payload.extend_from_slice(&TAIL);
+        let mut decompressed = Vec::with_capacity((payload.len() * 2).min(max_size + 1));
+        let start = self.decompress.total_in();
+        loop {
+            if decompressed.len() == decompressed.capacity() {
+                decompressed.reserve(decompressed.capacity().max(1024));
+            }
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let written = decompressed.len();
+            let status = self
+                .decompress
+                .decompress_vec(
+                    &payload[consumed..],
+                    &mut decompressed,
+                    FlushDecompress::Sync,
+                )
+                .map_err(|_| WebSocketError::Protocol("Invalid compressed message"))?;
+            if decompressed.len() > max_size {
+                return Err(WebSocketError::MessageTooBig);
+            }
+            let progress = (self.decompress.total_in() - start) as usize;
+            if status == Status::StreamEnd {
+                // The client ended the stream with a final block, only the added tail can follow
+                self.decompress.reset(false);
+                break;
+            }
+            if progress == payload.len() && decompressed.len() < decompressed.capacity() {
+                break;
+            }
+            if progress == consumed && decompressed.len() == written {
+                return Err(WebSocketError::Protocol("Invalid compressed message"));
+            }
+        }
+        if self.params.client_no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(decompressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::WebSocketError;
+
+    use super::{Deflate, DeflateParams};
+
+    fn negotiate(offers: &str) -> Option<String> {
+        DeflateParams::negotiate([offers].into_iter()).map(|params| params.to_string())
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(
+            negotiate("permessage-deflate; client_max_window_bits").unwrap(),
+            "permessage-deflate"
+        );
+        assert_eq!(
+            negotiate(
+                "permessage-deflate; server_no_context_takeover; client_no_context_takeover; \
+                 server_max_window_bits=\"10\""
+            )
+            .unwrap(),
+            "permessage-deflate; server_no_context_takeover; client_no_context_takeover; \
+             server_max_window_bits=10"
+        );
+        // Invalid offers are declined in favor of the next one
+        assert_eq!(
+            negotiate(
+                "x-webkit-deflate-frame, permessage-deflate; server_max_window_bits=8, \
+                 permessage-deflate; server_max_window_bits=9"
+            )
+            .unwrap(),
+            "permessage-deflate; server_max_window_bits=9"
+        );
+        for offers in [
+            "x-webkit-deflate-frame",
+            "permessage-deflate; server_max_window_bits",
+            "permessage-deflate; client_max_window_bits=16",
+            "permessage-deflate; server_no_context_takeover; server_no_context_takeover",
+            "permessage-deflate; server_no_context_takeover=1",
+            "permessage-deflate; unknown",
+        ] {
+            assert_eq!(negotiate(offers), None, "{offers}");
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let message = b"{\"type\":\"update\",\"value\":42}".repeat(100);
+        for params in [
+            DeflateParams::default(),
+            DeflateParams {
+                server_no_context_takeover: true,
+                client_no_context_takeover: true,
+                server_max_window_bits: Some(9),
+            },
+        ] {
+            let mut server = Deflate::new(params);
+            let mut client = Deflate::new(params);
+            for _ in 0..3 {
+                let compressed = server.compress(&message).unwrap();
+                assert!(compressed.len() < message.len() / 10);
+                let decompressed = client.decompress(compressed, message.len()).unwrap();
+                assert_eq!(decompressed, message);
+            }
+        }
+    }
+
+    #[test]
+    fn rfc_7692_examples() {
+        let mut deflate = Deflate::new(DeflateParams::default());
+        let hello = [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        assert_eq!(deflate.decompress(hello.to_vec(), 5).unwrap(), b"Hello");
+        // The second message refers to the first one
+        let hello = [0xf2, 0x00, 0x11, 0x00, 0x00];
+        assert_eq!(deflate.decompress(hello.to_vec(), 5).unwrap(), b"Hello");
+        // Compressed with a final block
+        let mut deflate = Deflate::new(DeflateParams::default());
+        let hello = [0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        assert_eq!(deflate.decompress(hello.to_vec(), 5).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn decompression_limit() {
+        let mut deflate = Deflate::new(DeflateParams::default());
+        let compressed = deflate.compress(&[0; 10_000]).unwrap();
+        assert!(matches!(
+            Deflate::new(DeflateParams::default()).decompress(compressed, 9_999),
+            Err(WebSocketError::MessageTooBig)
+        ));
+        assert!(matches!(
+            deflate.decompress(vec![0xFF, 0xFF, 0xFF], 100),
+            Err(WebSocketError::Protocol(_))
+        ));
+    }
+}
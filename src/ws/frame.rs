@@ -0,0 +1,219 @@
+//! WebSocket frames, as defined by RFC 6455
+
+use smol::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::WebSocketError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OpCode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl OpCode {
+    fn from_u8(opcode: u8) -> Option<Self> {
+        match opcode {
+            0x0 => Some(Self::Continuation),
+            0x1 => Some(Self::Text),
+            0x2 => Some(Self::Binary),
+            0x8 => Some(Self::Close),
+            0x9 => Some(Self::Ping),
+            0xA => Some(Self::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Self::Continuation => 0x0,
+            Self::Text => 0x1,
+            Self::Binary => 0x2,
+            Self::Close => 0x8,
+            Self::Ping => 0x9,
+            Self::Pong => 0xA,
+        }
+    }
+
+    pub(crate) fn is_control(self) -> bool {
+        matches!(self, Self::Close | Self::Ping | Self::Pong)
+    }
+}
+
+/// Control frames can't be fragmented nor have a bigger payload
+pub(crate) const MAX_CONTROL_PAYLOAD: usize = 125;
+
+#[derive(Debug)]
+pub(crate) struct Frame {
+    pub(crate) fin: bool,
+    /// Set on the first frame of a compressed message, see permessage-deflate
+    pub(crate) rsv1: bool,
+    pub(crate) opcode: OpCode,
+    pub(crate) payload: Vec<u8>,
+}
+
+impl Frame {
+    /// A frame holding a whole message
+    pub(crate) fn new(opcode: OpCode, payload: Vec<u8>) -> Self {
+        Self {
+            fin: true,
+            rsv1: false,
+            opcode,
+            payload,
+        }
+    }
+
+    /// Reads a frame sent by a client, which must be masked, and unmasks its payload.
+    ///
+    /// Payloads bigger than `max_payload` are rejected before being read.
+    pub(crate) async fn read<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        max_payload: usize,
+    ) -> Result<Self, WebSocketError> {
+        let mut head = [0; 2];
+        reader.read_exact(&mut head).await?;
+        let fin = head[0] & 0x80 != 0;
+        let rsv1 = head[0] & 0x40 != 0;
+        // No extension uses the other reserved bits
+        if head[0] & 0x30 != 0 {
+            return Err(WebSocketError::Protocol("Reserved bits set"));
+        }
+        let opcode =
+            OpCode::from_u8(head[0] & 0x0F).ok_or(WebSocketError::Protocol("Unknown opcode"))?;
+        if head[1] & 0x80 == 0 {
+            return Err(WebSocketError::Protocol("Unmasked frame"));
+        }
+
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0; 2];
+                reader.read_exact(&mut len).await?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0; 8];
+                reader.read_exact(&mut len).await?;
+                let len = u64::from_be_bytes(len);
+                if len >> 63 != 0 {
+                    return Err(WebSocketError::Protocol("Invalid payload length"));
+                }
+                len
+            }
+            len => len as u64,
+        };
+        if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(WebSocketError::Protocol(
+                "Fragmented or too long control frame",
+            ));
+        }
+        if len > max_payload as u64 {
+            return Err(WebSocketError::MessageTooBig);
+        }
+
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask).await?;
+        let mut payload = vec![0; len as usize];
+        reader.read_exact(&mut payload).await?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok(Self {
+            fin,
+            rsv1,
+            opcode,
+            payload,
+        })
+    }
+
+    /// Encodes the frame as sent by a server, i.e. unmasked
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.payload.len() + 10);
+        let fin = if self.fin { 0x80 } else { 0 };
+        let rsv1 = if self.rsv1 { 0x40 } else { 0 };
+        bytes.push(fin | rsv1 | self.opcode.as_u8());
+        match self.payload.len() {
+            len @ 0..=125 => bytes.push(len as u8),
+            len @ 126..=0xFFFF => {
+                bytes.push(126);
+                bytes.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                bytes.push(127);
+                bytes.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::WebSocketError;
+
+    use super::{Frame, OpCode};
+
+    fn read(bytes: &[u8]) -> Result<Frame, WebSocketError> {
+        smol::block_on(Frame::read(&mut &bytes[..], 1 << 20))
+    }
+
+    #[test]
+    fn masked_frames() {
+        // Examples of RFC 6455
+        let frame = read(&[
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ])
+        .unwrap();
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, OpCode::Text);
+        assert_eq!(frame.payload, b"Hello");
+
+        let frame = read(&[0x01, 0x83, 0, 0, 0, 0, 0x48, 0x65, 0x6c]).unwrap();
+        assert!(!frame.fin);
+        assert_eq!(frame.payload, b"Hel");
+    }
+
+    #[test]
+    fn payload_lengths() {
+        for len in [0, 125, 126, 0xFFFF, 0x10000] {
+            let frame = Frame::new(OpCode::Binary, vec![7; len]);
+            let mut bytes = frame.encode();
+            assert_eq!(bytes[0], 0x82);
+            // Masks the frame with a zero key, which leaves the payload unchanged
+            let header_len = bytes.len() - len;
+            bytes[1] |= 0x80;
+            bytes.splice(header_len..header_len, [0; 4]);
+            let frame = read(&bytes).unwrap();
+            assert_eq!(frame.payload.len(), len);
+        }
+    }
+
+    #[test]
+    fn invalid_frames() {
+        for bytes in [
+            // Unmasked
+            &[0x81, 0x00][..],
+            // Reserved bit
+            &[0xA1, 0x80, 0, 0, 0, 0],
+            // Unknown opcode
+            &[0x83, 0x80, 0, 0, 0, 0],
+            // Fragmented ping
+            &[0x09, 0x80, 0, 0, 0, 0],
+            // Ping longer than 125 bytes
+            &[0x89, 0xFE, 0x00, 0x7E],
+        ] {
+            assert!(
+                matches!(read(bytes), Err(WebSocketError::Protocol(_))),
+                "{bytes:?}"
+            );
+        }
+        assert!(matches!(
+            smol::block_on(Frame::read(&mut &[0x82, 0x8A][..], 9)),
+            Err(WebSocketError::MessageTooBig)
+        ));
+    }
+}